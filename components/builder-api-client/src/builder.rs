@@ -7,7 +7,8 @@ use crate::{error::{Error,
                                  PackageIdent,
                                  PackageTarget},
                        ChannelIdent},
-            hab_http::ApiClient,
+            hab_http::{ApiClient,
+                       ClientConfig},
             response::{err_from_response,
                        ResponseExt},
             BoxedClient,
@@ -132,13 +133,24 @@ impl BuilderAPIClient {
                   fs_root_path: Option<&Path>)
                   -> Result<Self>
         where U: IntoUrl
+    {
+        Self::with_config(endpoint,
+                          &ClientConfig::from_env(product, version)?,
+                          fs_root_path)
+    }
+
+    pub fn with_config<U>(endpoint: U,
+                          config: &ClientConfig,
+                          fs_root_path: Option<&Path>)
+                          -> Result<Self>
+        where U: IntoUrl
     {
         let mut endpoint = endpoint.into_url().map_err(Error::ReqwestError)?;
         if !endpoint.cannot_be_a_base() && endpoint.path() == "/" {
             endpoint.set_path(DEFAULT_API_PATH);
         }
         let client = BuilderAPIClient(
-            ApiClient::new(endpoint, product, version, fs_root_path)
+            ApiClient::with_config(endpoint, config, fs_root_path)
                 .map_err(Error::HabitatHttpClient)?,
         );
         Ok(client)
@@ -1166,7 +1178,23 @@ fn channel_package_demote(channel: &ChannelIdent, package: &PackageIdent) -> Str
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::hab_http;
+    use habitat_common::locked_env_var::LockedEnvVar;
+    use lazy_static::lazy_static;
     use serde_json;
+    use std::{io::Write,
+              net::TcpListener,
+              thread};
+
+    habitat_common::locked_env_var!(HAB_CLIENT_USER_AGENT_EXTRA, lock_user_agent_extra);
+    habitat_common::locked_env_var!(HAB_CLIENT_IDENTITY, lock_client_identity);
+    habitat_common::locked_env_var!(HAB_CLIENT_IDENTITY_PASSWORD, lock_client_identity_password);
+
+    /// Lock every variable `ClientConfig::from_env` reads, always in the same order, so a test
+    /// that builds a client never sees another test's settings.
+    fn lock_client_env() -> (LockedEnvVar, LockedEnvVar, LockedEnvVar) {
+        (lock_user_agent_extra(), lock_client_identity(), lock_client_identity_password())
+    }
 
     #[test]
     fn json_round_trip_u64_fields() {
@@ -1246,6 +1274,7 @@ mod tests {
 
     #[test]
     fn package_search() {
+        let _env = lock_client_env();
         let client = BuilderAPIClient::new("http://test.com", "", "", None).expect("valid client");
 
         let sample_data = vec!["one_a", "one_b", "one_c", "one_d", "one_e", "two_a", "two_b",
@@ -1280,9 +1309,136 @@ mod tests {
         assert_eq!(r.1, 0);
     }
 
+    /// Starts a server that answers one HTTP request with an empty `200 OK`. Returns the URL to
+    /// send the request to and a handle that yields the head of the request it received.
+    fn mock_server() -> (String, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock server");
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let handle = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().expect("accept request");
+            let mut request = Vec::new();
+            let mut buf = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let n = stream.read(&mut buf).expect("read request");
+                if n == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n")
+                  .expect("write response");
+            String::from_utf8(request).expect("request is UTF-8")
+        });
+        (url, handle)
+    }
+
+    #[test]
+    fn user_agent_extra_is_sent_with_requests() {
+        let (url, request) = mock_server();
+        let config = ClientConfig::new("hab", "0.88.0/20190101000000");
+        let config = config.user_agent_extra("deployment-east")
+                           .expect("valid user agent addition");
+        let client =
+            BuilderAPIClient::with_config(url.as_str(), &config, None).expect("valid client");
+
+        client.0.get("status").send().expect("request sent");
+
+        let user_agent = user_agent_of(request);
+        assert!(user_agent.starts_with("hab/0.88.0/20190101000000 ("),
+                "unexpected User-Agent: {}",
+                user_agent);
+        assert!(user_agent.ends_with(") deployment-east"),
+                "unexpected User-Agent: {}",
+                user_agent);
+    }
+
+    /// The `User-Agent` header of the request `request` handles.
+    fn user_agent_of(request: thread::JoinHandle<String>) -> String {
+        let request = request.join().expect("mock server finished");
+        let user_agent = request.lines()
+                                .find(|line| line.to_lowercase().starts_with("user-agent:"))
+                                .expect("request has a User-Agent header");
+        user_agent["user-agent:".len()..].trim().to_string()
+    }
+
+    #[test]
+    fn user_agent_extra_is_read_from_the_environment() {
+        let (url, request) = mock_server();
+        let (user_agent_extra, _identity, _password) = lock_client_env();
+        user_agent_extra.set("deployment-west");
+        let client =
+            BuilderAPIClient::new(url.as_str(), "hab", "0.88.0", None).expect("valid client");
+
+        client.0.get("status").send().expect("request sent");
+
+        let user_agent = user_agent_of(request);
+        assert!(user_agent.ends_with(") deployment-west"),
+                "unexpected User-Agent: {}",
+                user_agent);
+    }
+
+    #[test]
+    fn user_agent_extra_rejects_control_characters() {
+        for extra in &["east\nX-Injected: true", "east\r", "east\u{7f}"] {
+            match ClientConfig::new("hab", "0.88.0").user_agent_extra(extra) {
+                Err(hab_http::Error::InvalidUserAgent(ua)) => assert_eq!(&ua, extra),
+                other => panic!("Expected InvalidUserAgent for {:?}, got {:?}", extra, other),
+            }
+        }
+    }
+
+    fn client_identity_fixture() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests")
+                                                 .join("fixtures")
+                                                 .join("client-identity.p12")
+    }
+
+    #[test]
+    fn clients_are_built_with_a_client_identity() {
+        let (url, request) = mock_server();
+        let identity = fs::read(client_identity_fixture()).expect("read client identity");
+        let config = ClientConfig::new("hab", "0.88.0").client_identity(&identity, "secret")
+                                                       .expect("valid client identity");
+        let client =
+            BuilderAPIClient::with_config(url.as_str(), &config, None).expect("valid client");
+
+        client.0.get("status").send().expect("request sent");
+        request.join().expect("mock server finished");
+    }
+
+    #[test]
+    fn client_identity_is_read_from_the_environment() {
+        let (_user_agent_extra, identity, password) = lock_client_env();
+        identity.set(client_identity_fixture());
+        password.set("secret");
+
+        let config = ClientConfig::from_env("hab", "0.88.0").expect("valid client identity");
+        assert!(format!("{:?}", config).contains("identity: Some(ClientIdentity { .. })"),
+                "unexpected config: {:?}",
+                config);
+    }
+
+    #[test]
+    fn client_identity_rejects_the_wrong_password() {
+        let identity = fs::read(client_identity_fixture()).expect("read client identity");
+        match ClientConfig::new("hab", "0.88.0").client_identity(&identity, "wrong") {
+            Err(hab_http::Error::InvalidClientCertificate(_)) => {}
+            other => panic!("Expected InvalidClientCertificate, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn client_identity_rejects_data_that_is_not_pkcs12() {
+        match ClientConfig::new("hab", "0.88.0").client_identity(b"not a certificate", "secret") {
+            Err(hab_http::Error::InvalidClientCertificate(_)) => {}
+            other => panic!("Expected InvalidClientCertificate, got {:?}", other),
+        }
+    }
+
     #[test]
     #[ignore = "takes too long to run regularly; should run on CI"]
     fn package_search_large() {
+        let _env = lock_client_env();
         let client = BuilderAPIClient::new("http://test.com", "", "", None).expect("valid client");
 
         let count = 100_000;
@@ -27,8 +27,9 @@ use std::{fmt,
 use chrono::DateTime;
 use reqwest::IntoUrl;
 
-pub use crate::error::{Error,
-                       Result};
+pub use crate::{error::{Error,
+                        Result},
+                hab_http::ClientConfig};
 
 use crate::{builder::BuilderAPIClient,
             hab_core::{crypto::keys::box_key_pair::WrappedSealedBox,
@@ -411,10 +412,23 @@ impl Client {
                   fs_root_path: Option<&Path>)
                   -> Result<BoxedClient>
         where U: IntoUrl
+    {
+        Self::with_config(endpoint,
+                          &ClientConfig::from_env(product, version)?,
+                          fs_root_path)
+    }
+
+    /// Like `Client::new`, but with more control over the client, such as adding to the
+    /// `User-Agent` header sent with each request.
+    pub fn with_config<U>(endpoint: U,
+                          config: &ClientConfig,
+                          fs_root_path: Option<&Path>)
+                          -> Result<BoxedClient>
+        where U: IntoUrl
     {
         let endpoint = endpoint.into_url().map_err(Error::ReqwestError)?;
 
-        let client = BuilderAPIClient::new(endpoint, product, version, fs_root_path)?;
+        let client = BuilderAPIClient::with_config(endpoint, config, fs_root_path)?;
 
         Ok(Box::new(client))
     }
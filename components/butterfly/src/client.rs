@@ -3,6 +3,7 @@
 //! This will connect to a given butterfly members `Pull` thread, and inject a rumor.
 
 use habitat_core::{crypto::SymKey,
+                   env::Config,
                    service::ServiceGroup};
 use zmq;
 
@@ -13,6 +14,8 @@ use crate::{error::{Error,
                     service_config::ServiceConfig,
                     service_file::ServiceFile,
                     Rumor},
+            ServiceFileChunkBytes,
+            ServiceFileMaxBytes,
             ZMQ_CONTEXT};
 
 /// Holds a ZMQ Push socket, and an optional ring encryption key.
@@ -61,7 +64,11 @@ impl Client {
         self.send(&sc)
     }
 
-    /// Create a service file and send it to the server.
+    /// Create a service file and send it to the server, in chunks if chunking is turned on and it
+    /// is larger than `ServiceFileChunkBytes`.
+    ///
+    /// # Errors
+    /// * Error::ServiceFileTooLarge if the file is larger than `ServiceFileMaxBytes`
     pub fn send_service_file<S>(&mut self,
                                 service_group: ServiceGroup,
                                 filename: S,
@@ -74,7 +81,12 @@ impl Client {
         let mut sf = ServiceFile::new("butterflyclient", service_group, filename, body.to_vec());
         sf.incarnation = incarnation;
         sf.encrypted = encrypted;
-        self.send(&sf)
+        for chunk in sf.into_chunks(ServiceFileMaxBytes::configured_value().into(),
+                                    ServiceFileChunkBytes::configured_value().into())?
+        {
+            self.send(&chunk)?;
+        }
+        Ok(())
     }
 
     /// Send any `Rumor` to the server.
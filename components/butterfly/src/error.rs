@@ -8,6 +8,7 @@ use std::{error,
 
 use habitat_core;
 use prost;
+use serde_json;
 use toml;
 use zmq;
 
@@ -18,17 +19,35 @@ pub enum Error {
     BadDataPath(PathBuf, io::Error),
     BadDatFile(PathBuf, io::Error),
     CannotBind(io::Error),
+    DatFileBundle(PathBuf, serde_json::Error),
+    DatFileChecksumMismatch(PathBuf, u64, u32, u32),
+    DatFileCorruptRumor(PathBuf, u64),
     DatFileIO(PathBuf, io::Error),
+    DatFileInconsistentHeader(PathBuf, u64, u64),
+    DatFileMigration(PathBuf, String),
+    DatFileSealed(PathBuf),
+    DatFileSealedWithWal,
+    DatFileUnsealable(PathBuf, u64),
+    DatFileUnsupportedVersion(u8),
     DecodeError(prost::DecodeError),
     EncodeError(prost::EncodeError),
     HabitatCore(habitat_core::error::Error),
     IncarnationIO(PathBuf, io::Error),
     IncarnationParse(PathBuf, num::ParseIntError),
+    InvalidRumor(&'static str, &'static str),
     InvalidRumorShareLimit,
+    InvalidTimeoutOverride(String, &'static str),
     NonExistentRumor(String, String),
     ProtocolMismatch(&'static str),
+    RumorTooLarge(u64, usize),
     ServiceConfigDecode(String, toml::de::Error),
     ServiceConfigNotUtf8(String, str::Utf8Error),
+    ServiceFileHashMismatch(String, String),
+    ServiceFileTooLarge {
+        filename: String,
+        size:     usize,
+        limit:    usize,
+    },
     SocketCloneError,
     SocketSetReadTimeout(io::Error),
     SocketSetWriteTimeout(io::Error),
@@ -53,11 +72,57 @@ impl fmt::Display for Error {
                         err)
             }
             Error::CannotBind(ref err) => format!("Cannot bind to port: {:?}", err),
+            Error::DatFileBundle(ref path, ref err) => {
+                format!("Unable to encode or decode DatFile bundle, {}, {}",
+                        path.display(),
+                        err)
+            }
+            Error::DatFileChecksumMismatch(ref path, offset, recorded, computed) => {
+                format!("DatFile {} is corrupt; the region at offset {} has checksum {:08x}, but \
+                         its header recorded {:08x}",
+                        path.display(),
+                        offset,
+                        computed,
+                        recorded)
+            }
+            Error::DatFileCorruptRumor(ref path, offset) => {
+                format!("DatFile {} is corrupt; the rumor at offset {} is empty",
+                        path.display(),
+                        offset)
+            }
             Error::DatFileIO(ref path, ref err) => {
                 format!("Error reading or writing to DatFile, {}, {}",
                         path.display(),
                         err)
             }
+            Error::DatFileInconsistentHeader(ref path, header_size, file_size) => {
+                format!("DatFile {} is corrupt; its header claims to be {} bytes long, but the \
+                         file is only {} bytes long",
+                        path.display(),
+                        header_size,
+                        file_size)
+            }
+            Error::DatFileMigration(ref path, ref reason) => {
+                format!("Can't migrate DatFile {}: {}", path.display(), reason)
+            }
+            Error::DatFileSealed(ref path) => {
+                format!("DatFile {} is sealed, but no key was given to open it",
+                        path.display())
+            }
+            Error::DatFileSealedWithWal => {
+                "A sealed DatFile can't be combined with the unsealed write-ahead log".to_string()
+            }
+            Error::DatFileUnsealable(ref path, offset) => {
+                format!("DatFile {} is corrupt or was sealed with a different key; the region at \
+                         offset {} can't be opened",
+                        path.display(),
+                        offset)
+            }
+            Error::DatFileUnsupportedVersion(version) => {
+                format!("Unsupported DatFile version {}; it was likely written by a newer \
+                         Supervisor",
+                        version)
+            }
             Error::UnknownIOError(ref err) => format!("Error reading or writing: {}", err),
             Error::DecodeError(ref err) => format!("Failed to decode protocol message: {}", err),
             Error::EncodeError(ref err) => format!("Failed to encode protocol message: {}", err),
@@ -72,9 +137,15 @@ impl fmt::Display for Error {
                         path.display(),
                         err)
             }
+            Error::InvalidRumor(message_id, reason) => {
+                format!("Invalid {} rumor: {}", message_id, reason)
+            }
             Error::InvalidRumorShareLimit => {
                 "Rumor share limit should be a positive integer".to_string()
             }
+            Error::InvalidTimeoutOverride(ref value, reason) => {
+                format!("Invalid timeout override '{}': {}", value, reason)
+            }
             Error::NonExistentRumor(ref member_id, ref rumor_id) => {
                 format!("Non existent rumor asked to be written to bytes: {} {}",
                         member_id, rumor_id)
@@ -83,12 +154,28 @@ impl fmt::Display for Error {
                 format!("Received an unsupported or bad protocol message. Missing field: {}",
                         field)
             }
+            Error::RumorTooLarge(size, limit) => {
+                format!("Refusing to read a {} byte rumor; the limit is {} bytes (see \
+                         HAB_MAX_INBOUND_MESSAGE_BYTES)",
+                        size, limit)
+            }
             Error::ServiceConfigDecode(ref sg, ref err) => {
                 format!("Cannot decode service config: group={}, {:?}", sg, err)
             }
             Error::ServiceConfigNotUtf8(ref sg, ref err) => {
                 format!("Cannot read service configuration: group={}, {}", sg, err)
             }
+            Error::ServiceFileHashMismatch(ref sg, ref filename) => {
+                format!("The chunks of service file {} for {} do not match its content hash",
+                        filename, sg)
+            }
+            Error::ServiceFileTooLarge { ref filename,
+                                         size,
+                                         limit, } => {
+                format!("Service file {} is {} bytes; the limit is {} bytes (see \
+                         HAB_SERVICE_FILE_MAX_BYTES)",
+                        filename, size, limit)
+            }
             Error::SocketCloneError => "Cannot clone the underlying UDP socket".to_string(),
             Error::SocketSetReadTimeout(ref err) => {
                 format!("Cannot set UDP socket read timeout: {}", err)
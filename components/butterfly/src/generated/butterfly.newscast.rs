@@ -13,6 +13,8 @@ pub struct Election {
     pub status: ::std::option::Option<i32>,
     #[prost(string, repeated, tag="6")]
     pub votes: ::std::vec::Vec<std::string::String>,
+    #[prost(uint64, optional, tag="7")]
+    pub announce_timeout_ms: ::std::option::Option<u64>,
 }
 pub mod election {
     #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, ::prost::Enumeration)]
@@ -67,6 +69,14 @@ pub struct ServiceFile {
     pub filename: ::std::option::Option<std::string::String>,
     #[prost(bytes, optional, tag="5")]
     pub body: ::std::option::Option<std::vec::Vec<u8>>,
+    #[prost(uint32, optional, tag="6")]
+    pub chunk_index: ::std::option::Option<u32>,
+    #[prost(uint32, optional, tag="7")]
+    pub chunk_total: ::std::option::Option<u32>,
+    #[prost(uint64, optional, tag="8")]
+    pub content_length: ::std::option::Option<u64>,
+    #[prost(string, optional, tag="9")]
+    pub content_hash: ::std::option::Option<std::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 #[derive(Serialize, Deserialize)]
@@ -139,3 +149,12 @@ pub mod rumor {
         Departure(super::Departure),
     }
 }
+/// A Supervisor's gossip state, exported from its dat file so it can be imported on another host.
+#[derive(Clone, PartialEq, ::prost::Message)]
+#[derive(Serialize, Deserialize)]
+pub struct Bundle {
+    #[prost(message, repeated, tag="1")]
+    pub members: ::std::vec::Vec<super::swim::Membership>,
+    #[prost(message, repeated, tag="2")]
+    pub rumors: ::std::vec::Vec<Rumor>,
+}
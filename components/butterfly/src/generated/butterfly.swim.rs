@@ -16,6 +16,8 @@ pub struct Member {
     pub persistent: ::std::option::Option<bool>,
     #[prost(bool, optional, tag="7", default="false")]
     pub departed: ::std::option::Option<bool>,
+    #[prost(map="string, string", tag="8")]
+    pub labels: ::std::collections::HashMap<std::string::String, std::string::String>,
 }
 #[derive(Clone, PartialEq, ::prost::Message)]
 #[derive(Serialize, Deserialize)]
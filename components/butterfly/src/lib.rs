@@ -49,6 +49,44 @@ pub mod swim;
 pub use crate::server::Server;
 use std::cell::UnsafeCell;
 
+habitat_core::env_config_int!(/// The largest rumor, in bytes, that will be read from a dat file
+                              /// or accepted from a gossip peer. Larger rumors are rejected
+                              /// before any memory is allocated for them, so a corrupt or forged
+                              /// length prefix can't exhaust memory.
+                              #[derive(Debug)]
+                              MaxInboundMessageBytes,
+                              usize,
+                              HAB_MAX_INBOUND_MESSAGE_BYTES,
+                              4 * 1024 * 1024);
+
+habitat_core::env_config_int!(/// The largest service file, in bytes, that will be created or
+                              /// accepted from a gossip peer. For a chunked file this is the
+                              /// size of the whole file, not of each chunk.
+                              #[derive(Debug)]
+                              ServiceFileMaxBytes,
+                              usize,
+                              HAB_SERVICE_FILE_MAX_BYTES,
+                              512 * 1024);
+
+habitat_core::env_config_int!(/// The largest service file, in bytes, that is gossiped as a
+                              /// single rumor. Larger files are split into chunks of this size.
+                              /// Zero, the default, never splits files. Supervisors from before
+                              /// chunking would store each chunk as the whole file, so only set
+                              /// this once every member of the ring reassembles chunks.
+                              #[derive(Debug)]
+                              ServiceFileChunkBytes,
+                              usize,
+                              HAB_SERVICE_FILE_CHUNK_BYTES,
+                              0);
+
+habitat_core::env_config_duration!(/// How long members wait for the candidate of a running
+                                   /// election to finish it. If the candidate crashes before it
+                                   /// announces that it has won, the election is restarted once
+                                   /// this has passed. Zero disables the timeout.
+                                   ElectionWinnerAnnounceTimeout,
+                                   HAB_ELECTION_WINNER_ANNOUNCE_TIMEOUT_SECS => from_secs,
+                                   std::time::Duration::from_secs(120));
+
 lazy_static! {
     /// A threadsafe shared ZMQ context for consuming services.
     ///
@@ -15,6 +15,7 @@ use habitat_common::sync::{Lock,
                            WriteGuard};
 use habitat_core::util::ToI64;
 use prometheus::IntGaugeVec;
+use prost::Message as ProstMessage;
 use rand::{seq::{IteratorRandom,
                  SliceRandom},
            thread_rng};
@@ -42,6 +43,9 @@ use uuid::Uuid;
 /// How many nodes do we target when we need to run PingReq.
 const PINGREQ_TARGETS: usize = 5;
 
+/// The `Member::labels` key naming the zone (e.g. datacenter) a member runs in.
+pub const ZONE_LABEL: &str = "zone";
+
 lazy_static! {
     static ref PEER_HEALTH_COUNT: IntGaugeVec =
         register_int_gauge_vec!("hab_butterfly_peer_health_total",
@@ -129,16 +133,33 @@ pub type UuidSimple = String;
 /// representation.
 #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Member {
-    pub id:          String,
-    pub incarnation: Incarnation,
-    pub address:     String,
-    pub swim_port:   u16,
-    pub gossip_port: u16,
-    pub persistent:  bool,
-    pub departed:    bool,
+    pub id:             String,
+    pub incarnation:    Incarnation,
+    pub address:        String,
+    pub swim_port:      u16,
+    pub gossip_port:    u16,
+    pub persistent:     bool,
+    pub departed:       bool,
+    /// Free-form metadata about the member, such as the `zone` it runs in.
+    #[serde(default)]
+    pub labels:         HashMap<String, String>,
+    /// Fields a newer Supervisor sent that this one doesn't know, still encoded. They are
+    /// appended when this member is encoded as part of a `Membership` again.
+    #[serde(skip)]
+    pub unknown_fields: Vec<u8>,
 }
 
 impl Member {
+    /// The tags of the fields of the `Member` message in swim.proto.
+    const KNOWN_TAGS: &'static [u32] = &[1, 2, 3, 4, 5, 6, 7, 8];
+
+    /// Appends the encoded member, followed by its unknown fields, to `buf`.
+    fn write_to_buf(&self, buf: &mut Vec<u8>) -> Result<()> {
+        proto::Member::from(self.clone()).encode(buf)?;
+        buf.extend_from_slice(&self.unknown_fields);
+        Ok(())
+    }
+
     /// Returns the socket address of this member.
     ///
     /// # Panics
@@ -158,18 +179,20 @@ impl Member {
 
 impl Default for Member {
     fn default() -> Self {
-        Member { id:          Uuid::new_v4().to_simple_ref().to_string(),
-                 incarnation: Incarnation::default(),
+        Member { id:             Uuid::new_v4().to_simple_ref().to_string(),
+                 incarnation:    Incarnation::default(),
                  // TODO (CM): DANGER DANGER DANGER
                  // This is a lousy default, and suggests that the notion
                  // of a "default Member" doesn't make much sense.
                  //
                  // (Port numbers of 0 are also problematic.)
-                 address:     String::default(),
-                 swim_port:   0,
-                 gossip_port: 0,
-                 persistent:  false,
-                 departed:    false, }
+                 address:        String::default(),
+                 swim_port:      0,
+                 gossip_port:    0,
+                 persistent:     false,
+                 departed:       false,
+                 labels:         HashMap::new(),
+                 unknown_fields: Vec::new(), }
     }
 }
 
@@ -186,6 +209,8 @@ impl<'a> From<&'a &'a Member> for RumorKey {
 }
 
 impl From<Member> for proto::Member {
+    /// The member's unknown fields can't be carried by the protobuf struct, so they are dropped.
+    /// See `Membership::write_to_buf`.
     fn from(value: Member) -> Self {
         proto::Member { id:          Some(value.id),
                         incarnation: Some(value.incarnation.to_u64()),
@@ -193,14 +218,19 @@ impl From<Member> for proto::Member {
                         swim_port:   Some(value.swim_port.into()),
                         gossip_port: Some(value.gossip_port.into()),
                         persistent:  Some(value.persistent),
-                        departed:    Some(value.departed), }
+                        departed:    Some(value.departed),
+                        labels:      value.labels, }
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Membership {
-    pub member: Member,
-    pub health: Health,
+    pub member:         Member,
+    pub health:         Health,
+    /// Fields a newer Supervisor sent that this one doesn't know, still encoded. They are
+    /// appended when this membership is encoded again, so gossip doesn't drop them from the ring.
+    #[serde(skip)]
+    pub unknown_fields: Vec<u8>,
 }
 
 impl fmt::Display for Membership {
@@ -219,6 +249,28 @@ impl fmt::Display for Membership {
 }
 
 impl Membership {
+    /// The tags of the fields of the `Membership` message in swim.proto.
+    const KNOWN_TAGS: &'static [u32] = &[1, 2];
+    /// The tag of the `member` field of the `Membership` message in swim.proto.
+    const MEMBER_TAG: u32 = 1;
+
+    /// Sets the unknown fields of this membership, and of its member, from `bytes`, the encoded
+    /// membership it was decoded from. Prost drops them when decoding.
+    pub fn restore_unknown_fields(&mut self, bytes: &[u8]) -> Result<()> {
+        self.unknown_fields = protocol::unknown_fields(bytes, Self::KNOWN_TAGS)?;
+        self.member.unknown_fields =
+            match protocol::length_delimited_field(bytes, Self::MEMBER_TAG)? {
+                Some(member) => protocol::unknown_fields(member, Member::KNOWN_TAGS)?,
+                None => Vec::new(),
+            };
+        Ok(())
+    }
+
+    /// Whether this membership or its member has fields this Supervisor doesn't know.
+    pub fn has_unknown_fields(&self) -> bool {
+        !(self.unknown_fields.is_empty() && self.member.unknown_fields.is_empty())
+    }
+
     /// See MemberList::insert
     fn newer_or_less_healthy_than(&self,
                                   other_incarnation: Incarnation,
@@ -231,6 +283,32 @@ impl Membership {
 
 impl protocol::Message<proto::Membership> for Membership {
     const MESSAGE_ID: &'static str = "Membership";
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut membership = Membership::from_proto(proto::Membership::decode(bytes)?)?;
+        membership.restore_unknown_fields(bytes)?;
+        Ok(membership)
+    }
+
+    fn write_to_bytes(&self) -> Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        self.write_to_buf(&mut buf)?;
+        Ok(buf)
+    }
+
+    /// Prost can't encode unknown fields, so the member is encoded by hand along with its own,
+    /// followed by the rest of the membership and then the membership's.
+    fn write_to_buf(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let mut member = Vec::new();
+        self.member.write_to_buf(&mut member)?;
+        protocol::append_length_delimited_field(buf, Self::MEMBER_TAG, &member);
+        let envelope = proto::Membership { member: None,
+                                           health: Some(self.health as i32), };
+        envelope.encode(buf)?;
+        buf.extend_from_slice(&self.unknown_fields);
+        Ok(buf.len() - start)
+    }
 }
 
 impl From<Membership> for proto::Membership {
@@ -297,25 +375,28 @@ impl FromProto<proto::Member> for Member {
                     // two uses of our Member protobuf, or both.
                     address: proto.address.unwrap_or_default(),
 
-                    swim_port:   proto.swim_port
-                                      .and_then(as_port)
-                                      .ok_or(Error::ProtocolMismatch("swim-port"))?,
-                    gossip_port: proto.gossip_port
-                                      .and_then(as_port)
-                                      .ok_or(Error::ProtocolMismatch("gossip-port"))?,
-                    persistent:  proto.persistent.unwrap_or(false),
-                    departed:    proto.departed.unwrap_or(false), })
+                    swim_port:      proto.swim_port
+                                         .and_then(as_port)
+                                         .ok_or(Error::ProtocolMismatch("swim-port"))?,
+                    gossip_port:    proto.gossip_port
+                                         .and_then(as_port)
+                                         .ok_or(Error::ProtocolMismatch("gossip-port"))?,
+                    persistent:     proto.persistent.unwrap_or(false),
+                    departed:       proto.departed.unwrap_or(false),
+                    labels:         proto.labels,
+                    unknown_fields: Vec::new(), })
     }
 }
 
 impl FromProto<proto::Membership> for Membership {
     fn from_proto(proto: proto::Membership) -> Result<Self> {
-        Ok(Membership { member: proto.member
-                                     .ok_or(Error::ProtocolMismatch("member"))
-                                     .and_then(Member::from_proto)?,
-                        health: proto.health
-                                     .and_then(Health::from_i32)
-                                     .unwrap_or(Health::Alive), })
+        Ok(Membership { member:         proto.member
+                                             .ok_or(Error::ProtocolMismatch("member"))
+                                             .and_then(Member::from_proto)?,
+                        health:         proto.health
+                                             .and_then(Health::from_i32)
+                                             .unwrap_or(Health::Alive),
+                        unknown_fields: Vec::new(), })
     }
 }
 
@@ -334,6 +415,7 @@ mod member_list {
         pub member:            super::Member,
         pub health:            super::Health,
         pub health_updated_at: super::SteadyTime,
+        pub unknown_fields:    Vec<u8>,
     }
 }
 
@@ -497,13 +579,17 @@ impl MemberList {
     /// * `MemberList::entries` (write)
     // TODO (CM): why don't we just insert a membership record here?
     pub fn insert_mlw(&self, incoming_member: Member, incoming_health: Health) -> bool {
-        self.insert_membership_mlw(Membership { member: incoming_member,
-                                                health: incoming_health, })
+        self.insert_membership_mlw(Membership { member:         incoming_member,
+                                                health:         incoming_health,
+                                                unknown_fields: Vec::new(), })
     }
 
+    /// Like `insert_mlw`, but also keeps the membership's unknown fields, so they are passed on
+    /// when the member is gossiped.
+    ///
     /// # Locking (see locking.md)
     /// * `MemberList::entries` (write)
-    fn insert_membership_mlw(&self, incoming: Membership) -> bool {
+    pub fn insert_membership_mlw(&self, incoming: Membership) -> bool {
         // Is this clone necessary, or can a key be a reference to a field contained in the value?
         // Maybe the members we store should not contain the ID to reduce the duplication?
         let modified = match self.write_entries().entry(incoming.member.id.clone()) {
@@ -512,7 +598,8 @@ impl MemberList {
                 if incoming.newer_or_less_healthy_than(val.member.incarnation, val.health) {
                     *val = member_list::Entry { member:            incoming.member,
                                                 health:            incoming.health,
-                                                health_updated_at: SteadyTime::now(), };
+                                                health_updated_at: SteadyTime::now(),
+                                                unknown_fields:    incoming.unknown_fields, };
                     true
                 } else {
                     false
@@ -521,7 +608,8 @@ impl MemberList {
             hash_map::Entry::Vacant(entry) => {
                 entry.insert(member_list::Entry { member:            incoming.member,
                                                   health:            incoming.health,
-                                                  health_updated_at: SteadyTime::now(), });
+                                                  health_updated_at: SteadyTime::now(),
+                                                  unknown_fields:    incoming.unknown_fields, });
                 true
             }
         };
@@ -553,11 +641,7 @@ impl MemberList {
     /// # Locking (see locking.md)
     /// * `MemberList::entries` (read)
     fn calculate_peer_health_metrics_mlr(&self) {
-        let mut health_counts: HashMap<Health, i64> = HashMap::new();
-
-        for entry in self.read_entries().values() {
-            *health_counts.entry(entry.health).or_insert(0) += 1;
-        }
+        let health_counts = self.health_counts_mlr();
 
         for health in [Health::Alive,
                        Health::Suspect,
@@ -565,8 +649,34 @@ impl MemberList {
                        Health::Departed].iter()
         {
             PEER_HEALTH_COUNT.with_label_values(&[&health.to_string()])
-                             .set(*health_counts.get(health).unwrap_or(&0));
+                             .set(*health_counts.get(health).unwrap_or(&0) as i64);
+        }
+    }
+
+    /// Returns the number of members in each health state. States with no members are absent.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (read)
+    pub fn health_counts_mlr(&self) -> HashMap<Health, usize> {
+        let mut health_counts = HashMap::new();
+
+        for entry in self.read_entries().values() {
+            *health_counts.entry(entry.health).or_insert(0) += 1;
         }
+
+        health_counts
+    }
+
+    /// A copy of every member whose health is `health`, taken under a single read lock.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (read)
+    pub fn members_with_health_mlr(&self, health: Health) -> Vec<Member> {
+        self.read_entries()
+            .values()
+            .filter(|entry| entry.health == health)
+            .map(|member_list::Entry { member, .. }| member.clone())
+            .collect()
     }
 
     /// Returns the health of the member, if the member exists.
@@ -643,10 +753,14 @@ impl MemberList {
     pub fn membership_for_mlr(&self, member_id: &str) -> Option<Membership> {
         self.read_entries()
             .get(member_id)
-            .map(|member_list::Entry { member, health, .. }| {
-                Membership { member: member.clone(),
-                             health: *health, }
-            })
+            .map(|member_list::Entry { member,
+                                       health,
+                                       unknown_fields,
+                                       .. }| {
+                     Membership { member:         member.clone(),
+                                  health:         *health,
+                                  unknown_fields: unknown_fields.clone(), }
+                 })
     }
 
     /// Returns the number of entries.
@@ -722,10 +836,14 @@ impl MemberList {
         let mut ok = Ok(T::default());
         for membership in self.read_entries()
                               .values()
-                              .map(|member_list::Entry { member, health, .. }| {
-                                  Membership { member: member.clone(),
-                                               health: *health, }
-                              })
+                              .map(|member_list::Entry { member,
+                                                         health,
+                                                         unknown_fields,
+                                                         .. }| {
+                                       Membership { member:         member.clone(),
+                                                    health:         *health,
+                                                    unknown_fields: unknown_fields.clone(), }
+                                   })
         {
             ok = Ok(with_closure(membership)?);
         }
@@ -733,24 +851,57 @@ impl MemberList {
     }
 
     /// Query the list of aging Suspect members to find those which
-    /// have now expired to Confirmed. Health is updated
+    /// have expired to Confirmed as of `now`. Health is updated
     /// appropriately, and a list of newly-Confirmed Member IDs is
     /// returned.
     ///
     /// # Locking (see locking.md)
     /// * `MemberList::entries` (write)
-    pub fn members_expired_to_confirmed_mlw(&self, timeout: Duration) -> Vec<String> {
-        self.members_expired_to_mlw(Health::Confirmed, timeout)
+    pub fn members_expired_to_confirmed_mlw(&self,
+                                            timeout: Duration,
+                                            now: SteadyTime)
+                                            -> Vec<String> {
+        self.members_expired_to_mlw(Health::Confirmed, |_| timeout, now)
+    }
+
+    /// Like `members_expired_to_confirmed_mlw`, but each member's
+    /// timeout is given by `timeout_for`, so that members can expire
+    /// on different schedules.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (write)
+    pub fn members_expired_to_confirmed_per_member_mlw(&self,
+                                                       timeout_for: impl Fn(&Member) -> Duration,
+                                                       now: SteadyTime)
+                                                       -> Vec<String> {
+        self.members_expired_to_mlw(Health::Confirmed, timeout_for, now)
     }
 
     /// Query the list of aging Confirmed members to find those which
-    /// have now expired to Departed. Health is updated appropriately,
-    /// and a list of newly-Departed Member IDs is returned.
+    /// have expired to Departed as of `now`. Health is updated
+    /// appropriately, and a list of newly-Departed Member IDs is
+    /// returned.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (write)
+    pub fn members_expired_to_departed_mlw(&self,
+                                           timeout: Duration,
+                                           now: SteadyTime)
+                                           -> Vec<String> {
+        self.members_expired_to_mlw(Health::Departed, |_| timeout, now)
+    }
+
+    /// Like `members_expired_to_departed_mlw`, but each member's
+    /// timeout is given by `timeout_for`, so that members can expire
+    /// on different schedules.
     ///
     /// # Locking (see locking.md)
     /// * `MemberList::entries` (write)
-    pub fn members_expired_to_departed_mlw(&self, timeout: Duration) -> Vec<String> {
-        self.members_expired_to_mlw(Health::Departed, timeout)
+    pub fn members_expired_to_departed_per_member_mlw(&self,
+                                                      timeout_for: impl Fn(&Member) -> Duration,
+                                                      now: SteadyTime)
+                                                      -> Vec<String> {
+        self.members_expired_to_mlw(Health::Departed, timeout_for, now)
     }
 
     /// Return the member IDs of all members that have "timed out" to
@@ -758,18 +909,22 @@ impl MemberList {
     ///
     /// For instance,
     ///
-    ///   members_expired_to(Health::Departed, timeout)
+    ///   members_expired_to(Health::Departed, timeout_for, now)
     ///
     /// will return the IDs of those members that have been
-    /// `Confirmed` for longer than the given `timeout`.
+    /// `Confirmed` for longer than `timeout_for` gives for them at
+    /// `now`.
     ///
     /// The newly-updated health status is recorded properly.
     ///
     /// # Locking (see locking.md)
     /// * `MemberList::entries` (write)
     // TODO (CM): Better return type than Vec<String>
-    fn members_expired_to_mlw(&self, expiring_to: Health, timeout: Duration) -> Vec<String> {
-        let now = SteadyTime::now();
+    fn members_expired_to_mlw(&self,
+                              expiring_to: Health,
+                              timeout_for: impl Fn(&Member) -> Duration,
+                              now: SteadyTime)
+                              -> Vec<String> {
         let precursor_health = match expiring_to {
             Health::Confirmed => Health::Suspect,
             Health::Departed => Health::Confirmed,
@@ -780,6 +935,7 @@ impl MemberList {
             self.write_entries()
                 .iter_mut()
                 .filter_map(|(id, v)| {
+                    let timeout = timeout_for(&v.member);
                     let member_list::Entry { health,
                                              health_updated_at,
                                              .. } = v;
@@ -894,12 +1050,15 @@ mod tests {
         use crate::{member::{Health,
                              Member,
                              Membership},
-                    protocol::Message};
+                    protocol::{swim as proto,
+                               Message}};
+        use prost::Message as ProstMessage;
         #[test]
         fn encode_decode_roundtrip() {
             let member = Member::default();
             let membership = Membership { member,
-                                          health: Health::Suspect };
+                                          health: Health::Suspect,
+                                          unknown_fields: Vec::new() };
 
             let bytes = membership.clone()
                                   .write_to_bytes()
@@ -910,6 +1069,54 @@ mod tests {
             assert_eq!(&membership.member, &from_bytes.member);
             assert_eq!(&membership.health, &from_bytes.health);
         }
+
+        #[test]
+        fn unknown_fields_survive_a_roundtrip() {
+            let membership = Membership { member:         Member::default(),
+                                          health:         Health::Confirmed,
+                                          unknown_fields: Vec::new(), };
+            let mut bytes = membership.write_to_bytes().unwrap();
+            // What a newer Supervisor might add: a varint field 9 = 42, and a string field
+            // 10 = "new"
+            let unknown_fields = vec![9 << 3, 42, 10 << 3 | 2, 3, b'n', b'e', b'w'];
+            bytes.extend_from_slice(&unknown_fields);
+
+            let decoded = Membership::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.member, membership.member);
+            assert_eq!(decoded.health, Health::Confirmed);
+            assert_eq!(decoded.unknown_fields, unknown_fields);
+
+            let roundtripped = Membership::from_bytes(&decoded.write_to_bytes().unwrap()).unwrap();
+            assert_eq!(roundtripped.member, membership.member);
+            assert_eq!(roundtripped.health, Health::Confirmed);
+            assert_eq!(roundtripped.unknown_fields, unknown_fields);
+        }
+
+        #[test]
+        fn member_unknown_fields_survive_a_roundtrip() {
+            // What a newer Supervisor might add to the member: a varint field 9 = 42
+            let member = Member { unknown_fields: vec![9 << 3, 42],
+                                  ..Member::default() };
+            let membership = Membership { member,
+                                          health: Health::Alive,
+                                          unknown_fields: Vec::new() };
+
+            let decoded = Membership::from_bytes(&membership.write_to_bytes().unwrap()).unwrap();
+            assert_eq!(decoded.member.unknown_fields, vec![9 << 3, 42]);
+            assert_eq!(decoded.member, membership.member);
+            assert!(decoded.unknown_fields.is_empty());
+        }
+
+        #[test]
+        fn memberships_without_unknown_fields_encode_as_prost_does() {
+            let membership = Membership { member:         Member::default(),
+                                          health:         Health::Suspect,
+                                          unknown_fields: Vec::new(), };
+            let proto: proto::Membership = membership.clone().into();
+            let mut expected = Vec::new();
+            proto.encode(&mut expected).unwrap();
+            assert_eq!(membership.write_to_bytes().unwrap(), expected);
+        }
     }
 
     mod member_list {
@@ -968,6 +1175,25 @@ mod tests {
               .ok();
         }
 
+        #[test]
+        fn health_counts_counts_members_in_each_health() {
+            let ml = MemberList::new();
+            for (health, count) in &[(Health::Alive, 3),
+                                     (Health::Suspect, 1),
+                                     (Health::Departed, 2)]
+            {
+                for _ in 0..*count {
+                    ml.insert_mlw(Member::default(), *health);
+                }
+            }
+
+            let counts = ml.health_counts_mlr();
+            assert_eq!(counts.get(&Health::Alive), Some(&3));
+            assert_eq!(counts.get(&Health::Suspect), Some(&1));
+            assert_eq!(counts.get(&Health::Confirmed), None);
+            assert_eq!(counts.get(&Health::Departed), Some(&2));
+        }
+
         #[test]
         fn pingreq_targets() {
             let ml = populated_member_list(10);
@@ -1407,7 +1633,8 @@ mod tests {
                                 MemberList};
             use std::{thread,
                       time::Duration as StdDuration};
-            use time::Duration;
+            use time::{Duration,
+                       SteadyTime};
 
             #[test]
             fn timing_out_from_suspect_to_confirmed() {
@@ -1422,27 +1649,28 @@ mod tests {
                 let large_timeout =
                     Duration::from_std(StdDuration::from_secs(large_seconds)).unwrap();
 
-                assert!(ml.members_expired_to_confirmed_mlw(small_timeout)
+                assert!(ml.members_expired_to_confirmed_mlw(small_timeout, SteadyTime::now())
                           .is_empty(),
                         "An empty MemberList shouldn't have anything that's timing out to being \
                          Confirmed");
 
                 assert!(ml.insert_mlw(member_one.clone(), Health::Alive));
 
-                assert!(ml.members_expired_to_confirmed_mlw(small_timeout)
+                assert!(ml.members_expired_to_confirmed_mlw(small_timeout, SteadyTime::now())
                           .is_empty(),
                         "Should be no newly Confirmed members when they're all Alive");
 
                 assert!(ml.insert_mlw(member_one.clone(), Health::Suspect));
 
-                assert!(ml.members_expired_to_confirmed_mlw(large_timeout)
+                assert!(ml.members_expired_to_confirmed_mlw(large_timeout, SteadyTime::now())
                           .is_empty(),
                         "Nothing should have timed out to Confirmed with a large timeout");
 
                 // Allow the Suspect to age
                 thread::sleep(StdDuration::from_secs(small_seconds));
 
-                let newly_confirmed = ml.members_expired_to_confirmed_mlw(small_timeout);
+                let newly_confirmed =
+                    ml.members_expired_to_confirmed_mlw(small_timeout, SteadyTime::now());
                 assert!(newly_confirmed.contains(&member_one.id),
                         "Member should be newly Confirmed after timing out");
 
@@ -1464,30 +1692,36 @@ mod tests {
                 let large_timeout =
                     Duration::from_std(StdDuration::from_secs(large_seconds)).unwrap();
 
-                assert!(ml.members_expired_to_departed_mlw(small_timeout).is_empty(),
+                assert!(ml.members_expired_to_departed_mlw(small_timeout, SteadyTime::now())
+                          .is_empty(),
                         "An empty MemberList shouldn't have anything that's timing out to being \
                          Departed");
 
                 assert!(ml.insert_mlw(member_one.clone(), Health::Alive));
-                assert!(ml.members_expired_to_departed_mlw(small_timeout).is_empty(),
+                assert!(ml.members_expired_to_departed_mlw(small_timeout, SteadyTime::now())
+                          .is_empty(),
                         "Should be no newly Departed members when they're all Alive");
 
                 assert!(ml.insert_mlw(member_one.clone(), Health::Suspect));
-                assert!(ml.members_expired_to_departed_mlw(small_timeout).is_empty(),
+                assert!(ml.members_expired_to_departed_mlw(small_timeout, SteadyTime::now())
+                          .is_empty(),
                         "Should be no newly Departed members when they're all Confirmed");
 
                 assert!(ml.insert_mlw(member_one.clone(), Health::Confirmed));
 
-                assert!(ml.members_expired_to_departed_mlw(small_timeout).is_empty(),
+                assert!(ml.members_expired_to_departed_mlw(small_timeout, SteadyTime::now())
+                          .is_empty(),
                         "Should be no newly Departed members when they're all Confirmed");
 
-                assert!(ml.members_expired_to_departed_mlw(large_timeout).is_empty(),
+                assert!(ml.members_expired_to_departed_mlw(large_timeout, SteadyTime::now())
+                          .is_empty(),
                         "Nothing should have timed out to Departed with a large timeout");
 
                 // Allow the Confirmed to age
                 thread::sleep(StdDuration::from_secs(small_seconds));
 
-                let newly_departed = ml.members_expired_to_departed_mlw(small_timeout);
+                let newly_departed =
+                    ml.members_expired_to_departed_mlw(small_timeout, SteadyTime::now());
                 assert!(newly_departed.contains(&member_one.id),
                         "Member should be newly Departed after timing out");
 
@@ -1496,6 +1730,29 @@ mod tests {
                            "Member should have a health of Departed after timing out");
             }
 
+            #[test]
+            fn expiration_is_measured_against_the_given_time() {
+                let ml = MemberList::new();
+                let member_one = Member::default();
+                let timeout = Duration::seconds(30);
+                let inserted_at = SteadyTime::now();
+                assert!(ml.insert_mlw(member_one.clone(), Health::Suspect));
+
+                assert!(ml.members_expired_to_confirmed_mlw(timeout, inserted_at)
+                          .is_empty(),
+                        "Nothing should have timed out before the timeout has passed");
+
+                let past_timeout = inserted_at + timeout + Duration::seconds(1);
+                assert_eq!(ml.members_expired_to_confirmed_mlw(timeout, past_timeout),
+                           vec![member_one.id.clone()]);
+                assert_eq!(ml.health_of_mlr(&member_one), Some(Health::Confirmed));
+
+                let past_departure = past_timeout + timeout + Duration::seconds(1);
+                assert_eq!(ml.members_expired_to_departed_mlw(timeout, past_departure),
+                           vec![member_one.id.clone()]);
+                assert_eq!(ml.health_of_mlr(&member_one), Some(Health::Departed));
+            }
+
             #[test]
             fn suspect_timeout_is_appropriately_selective() {
                 let ml = MemberList::new();
@@ -1511,7 +1768,8 @@ mod tests {
 
                 let timeout = Duration::from_std(StdDuration::from_secs(2)).unwrap();
 
-                let newly_confirmed = ml.members_expired_to_confirmed_mlw(timeout);
+                let newly_confirmed =
+                    ml.members_expired_to_confirmed_mlw(timeout, SteadyTime::now());
                 assert!(newly_confirmed.contains(&member_1.id),
                         "Member 1 should be newly Confirmed after timing out");
                 assert!(newly_confirmed.contains(&member_2.id),
@@ -1546,7 +1804,7 @@ mod tests {
 
                 let timeout = Duration::from_std(StdDuration::from_secs(2)).unwrap();
 
-                let newly_departed = ml.members_expired_to_departed_mlw(timeout);
+                let newly_departed = ml.members_expired_to_departed_mlw(timeout, SteadyTime::now());
                 assert!(newly_departed.contains(&member_1.id),
                         "Member 1 should be newly Departed after timing out");
                 assert!(newly_departed.contains(&member_2.id),
@@ -32,3 +32,42 @@ pub fn unwrap_wire(payload: &[u8], ring_key: Option<&SymKey>) -> Result<Vec<u8>>
         Ok(payload)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ring_key() -> SymKey {
+        SymKey::generate_pair_for_ring("wolverine").expect("ring key generated")
+    }
+
+    #[test]
+    fn wire_sealed_with_the_ring_key_unwraps() {
+        let ring_key = ring_key();
+        let wire = generate_wire(b"gossip".to_vec(), Some(&ring_key)).expect("wire generated");
+
+        assert_eq!(unwrap_wire(&wire, Some(&ring_key)).expect("wire unwrapped"),
+                   b"gossip".to_vec());
+    }
+
+    #[test]
+    fn wire_sealed_with_another_ring_key_is_rejected() {
+        let wire = generate_wire(b"gossip".to_vec(), Some(&ring_key())).expect("wire generated");
+
+        assert!(unwrap_wire(&wire, Some(&ring_key())).is_err());
+    }
+
+    #[test]
+    fn tampered_wire_is_rejected() {
+        let ring_key = ring_key();
+        let sealed = generate_wire(b"gossip".to_vec(), Some(&ring_key)).expect("wire generated");
+        let mut wire = Wire::decode(&sealed[..]).expect("wire decoded");
+        if let Some(ref mut payload) = wire.payload {
+            payload[0] ^= 1;
+        }
+        let mut tampered = BytesMut::with_capacity(wire.encoded_len());
+        wire.encode(&mut tampered).expect("wire encoded");
+
+        assert!(unwrap_wire(&tampered, Some(&ring_key)).is_err());
+    }
+}
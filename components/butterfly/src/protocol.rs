@@ -2,10 +2,12 @@ pub mod newscast;
 pub mod swim;
 
 use bytes::BytesMut;
-use prost::Message as ProstMessage;
+use prost::{encoding,
+            Message as ProstMessage};
 use serde::Serialize;
 
-use crate::error::Result;
+use crate::error::{Error,
+                   Result};
 
 include!("generated/butterfly.common.rs");
 
@@ -23,8 +25,114 @@ pub trait Message<T: ProstMessage + Default>: FromProto<T> + Clone + Into<T> + S
         envelope.encode(&mut buf)?;
         Ok(buf.to_vec())
     }
+
+    /// Append the encoded message to `buf`, returning the number of bytes appended.
+    fn write_to_buf(&self, buf: &mut Vec<u8>) -> Result<usize> {
+        let envelope = self.clone().into();
+        let len = envelope.encoded_len();
+        buf.reserve(len);
+        envelope.encode(buf)?;
+        Ok(len)
+    }
 }
 
 pub trait FromProto<T>: Sized {
     fn from_proto(value: T) -> Result<Self>;
 }
+
+/// The raw encoding of every field in the encoded message `bytes` whose tag is not in
+/// `known_tags`, in the order they appear. Prost drops fields it doesn't know when decoding, so
+/// a message that must pass on fields added by newer Supervisors keeps these bytes and appends
+/// them when it is encoded again.
+pub fn unknown_fields(bytes: &[u8], known_tags: &[u32]) -> Result<Vec<u8>> {
+    let mut unknown = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let field = next_field(rest)?;
+        let (encoded, tail) = rest.split_at(field.len);
+        if !known_tags.contains(&field.tag) {
+            unknown.extend_from_slice(encoded);
+        }
+        rest = tail;
+    }
+    Ok(unknown)
+}
+
+/// The contents of the last length-delimited field in the encoded message `bytes` with the
+/// given tag, if there is one.
+pub fn length_delimited_field(bytes: &[u8], tag: u32) -> Result<Option<&[u8]>> {
+    Ok(length_delimited_fields(bytes, tag)?.pop())
+}
+
+/// The contents of every length-delimited field in the encoded message `bytes` with the given
+/// tag, in the order they appear, such as the elements of a repeated message field.
+pub fn length_delimited_fields(bytes: &[u8], tag: u32) -> Result<Vec<&[u8]>> {
+    let mut contents = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let field = next_field(rest)?;
+        if field.tag == tag && field.wire_type == WIRE_TYPE_LENGTH_DELIMITED {
+            contents.push(&rest[field.header_len..field.len]);
+        }
+        rest = &rest[field.len..];
+    }
+    Ok(contents)
+}
+
+/// Appends a length-delimited field with the given tag and contents to `buf`.
+pub fn append_length_delimited_field(buf: &mut Vec<u8>, tag: u32, contents: &[u8]) {
+    encoding::encode_key(tag, encoding::WireType::LengthDelimited, buf);
+    encoding::encode_varint(contents.len() as u64, buf);
+    buf.extend_from_slice(contents);
+}
+
+const WIRE_TYPE_VARINT: u64 = 0;
+const WIRE_TYPE_SIXTY_FOUR_BIT: u64 = 1;
+const WIRE_TYPE_LENGTH_DELIMITED: u64 = 2;
+const WIRE_TYPE_THIRTY_TWO_BIT: u64 = 5;
+
+/// Where a single field sits at the start of an encoded message.
+struct Field {
+    tag:        u32,
+    wire_type:  u64,
+    /// The length of the field's key, plus its length prefix if it is length-delimited
+    header_len: usize,
+    /// The length of the whole field, including its header
+    len:        usize,
+}
+
+fn next_field(bytes: &[u8]) -> Result<Field> {
+    let (key, key_len) = decode_varint(bytes)?;
+    let wire_type = key & 0x7;
+    let tag = (key >> 3) as u32;
+    let (header_len, value_len) = match wire_type {
+        WIRE_TYPE_VARINT => (key_len, decode_varint(&bytes[key_len..])?.1),
+        WIRE_TYPE_SIXTY_FOUR_BIT => (key_len, 8),
+        WIRE_TYPE_LENGTH_DELIMITED => {
+            let (value_len, prefix_len) = decode_varint(&bytes[key_len..])?;
+            (key_len + prefix_len, value_len as usize)
+        }
+        WIRE_TYPE_THIRTY_TWO_BIT => (key_len, 4),
+        // Groups are deprecated and none of our protocols use them
+        _ => return Err(Error::ProtocolMismatch("unsupported wire type")),
+    };
+    let len = header_len.checked_add(value_len)
+                        .filter(|&len| len <= bytes.len())
+                        .ok_or(Error::ProtocolMismatch("truncated field"))?;
+    Ok(Field { tag,
+               wire_type,
+               header_len,
+               len })
+}
+
+/// Decodes the varint at the start of `bytes`, returning its value and encoded length.
+fn decode_varint(bytes: &[u8]) -> Result<(u64, usize)> {
+    let mut value = 0;
+    for (i, &byte) in bytes.iter().take(10).enumerate() {
+        value |= u64::from(byte & 0x7f) << (7 * i);
+        if byte & 0x80 == 0 {
+            return Ok((value, i + 1));
+        }
+    }
+    Err(Error::ProtocolMismatch("invalid varint"))
+}
@@ -42,12 +42,14 @@ impl From<CDeparture> for Rumor {
 
 impl From<CElection> for Rumor {
     fn from(value: CElection) -> Self {
-        let payload = Election { member_id:     Some(value.member_id.clone()),
-                                 service_group: Some(value.service_group.to_string()),
-                                 term:          Some(value.term),
-                                 suitability:   Some(value.suitability),
-                                 status:        Some(value.status as i32),
-                                 votes:         value.votes.clone(), };
+        let payload = Election { member_id:           Some(value.member_id.clone()),
+                                 service_group:       Some(value.service_group.to_string()),
+                                 term:                Some(value.term),
+                                 suitability:         Some(value.suitability),
+                                 status:              Some(value.status as i32),
+                                 votes:               value.votes.clone(),
+                                 announce_timeout_ms: Some(value.announce_timeout.as_millis()
+                                                           as u64), };
         Rumor { r#type:  RumorType::Election as i32,
                 tag:     Vec::default(),
                 from_id: Some(value.member_id),
@@ -57,12 +59,14 @@ impl From<CElection> for Rumor {
 
 impl From<CElectionUpdate> for Rumor {
     fn from(value: CElectionUpdate) -> Self {
-        let payload = Election { member_id:     Some(value.member_id.clone()),
-                                 service_group: Some(value.service_group.to_string()),
-                                 term:          Some(value.term),
-                                 suitability:   Some(value.suitability),
-                                 status:        Some(value.status as i32),
-                                 votes:         value.votes.clone(), };
+        let payload = Election { member_id:           Some(value.member_id.clone()),
+                                 service_group:       Some(value.service_group.to_string()),
+                                 term:                Some(value.term),
+                                 suitability:         Some(value.suitability),
+                                 status:              Some(value.status as i32),
+                                 votes:               value.votes.clone(),
+                                 announce_timeout_ms: Some(value.announce_timeout.as_millis()
+                                                           as u64), };
         Rumor { r#type:  RumorType::ElectionUpdate as i32,
                 tag:     Vec::default(),
                 from_id: Some(value.member_id.clone()),
@@ -101,14 +105,11 @@ impl From<CServiceConfig> for Rumor {
 
 impl From<CServiceFile> for Rumor {
     fn from(value: CServiceFile) -> Self {
-        let payload = ServiceFile { service_group: Some(value.service_group.to_string()),
-                                    incarnation:   Some(value.incarnation),
-                                    encrypted:     Some(value.encrypted),
-                                    filename:      Some(value.filename),
-                                    body:          Some(value.body), };
+        let from_id = value.from_id.clone();
+        let payload = ServiceFile::from(value);
         Rumor { r#type:  RumorType::ServiceFile as i32,
                 tag:     Vec::default(),
-                from_id: Some(value.from_id),
+                from_id: Some(from_id),
                 payload: Some(RumorPayload::ServiceFile(payload)), }
     }
 }
@@ -10,6 +10,7 @@ pub mod dat_file;
 pub mod departure;
 pub mod election;
 pub mod heat;
+pub mod persistence;
 pub mod service;
 pub mod service_config;
 pub mod service_file;
@@ -17,7 +18,8 @@ pub mod service_file;
 use crate::{error::{Error,
                     Result},
             member::Membership,
-            protocol::{FromProto,
+            protocol::{self,
+                       FromProto,
                        Message},
             rumor::election::ElectionRumor};
 use bytes::BytesMut;
@@ -29,9 +31,11 @@ use std::{collections::{hash_map::Entry,
           default::Default,
           fmt,
           result,
-          sync::{atomic::{AtomicUsize,
+          sync::{atomic::{AtomicU64,
+                          AtomicUsize,
                           Ordering},
-                 Arc}};
+                 Arc},
+          time::Instant};
 
 pub use self::{departure::Departure,
                election::{Election,
@@ -40,6 +44,7 @@ pub use self::{departure::Departure,
                service_config::ServiceConfig,
                service_file::ServiceFile,
                storage::{RumorStore,
+                         RumorStoreLockMetrics,
                          RumorStoreProxy}};
 pub use crate::protocol::newscast::{Rumor as ProtoRumor,
                                     RumorPayload,
@@ -144,7 +149,8 @@ type RumorMap<T> = HashMap<RumorKeyKey, RumorSubMap<T>>;
 mod storage {
     use super::*;
     use habitat_common::sync::{Lock,
-                               ReadGuard};
+                               ReadGuard,
+                               WriteGuard};
     use serde::{ser::{SerializeMap,
                       SerializeSeq,
                       SerializeStruct},
@@ -189,7 +195,7 @@ mod storage {
     // functions require the contained value to be a rumor, so we use T, not R. Rumor-specific
     // functionality is a different impl block.
     impl<'a, T> IterableGuard<'a, RumorMap<T>> {
-        fn read(lock: &'a Lock<RumorMap<T>>) -> Self { IterableGuard(lock.read()) }
+        fn read(store: &'a RumorStore<T>) -> Self { IterableGuard(store.read_list()) }
 
         /// Allows iterator access to the rumors in to the `RumorMap` while holding its lock:
         /// ```
@@ -276,8 +282,53 @@ mod storage {
     pub struct RumorStore<T> {
         list:           Arc<Lock<RumorMap<T>>>,
         update_counter: Arc<AtomicUsize>,
+        lock_metrics:   Arc<RumorStoreLockMetrics>,
     }
 
+    /// How long callers have waited to lock a `RumorStore`'s list, to show when contention on it
+    /// is slowing gossip down. Every wait is counted, including the ones too short to notice.
+    #[derive(Debug, Default)]
+    pub struct RumorStoreLockMetrics {
+        total_read_wait_us:  AtomicU64,
+        total_write_wait_us: AtomicU64,
+        max_write_wait_us:   AtomicU64,
+    }
+
+    impl RumorStoreLockMetrics {
+        /// Total microseconds spent waiting to read the list.
+        pub fn total_read_wait_us(&self) -> u64 { self.total_read_wait_us.load(Ordering::Relaxed) }
+
+        /// Total microseconds spent waiting to write the list.
+        pub fn total_write_wait_us(&self) -> u64 {
+            self.total_write_wait_us.load(Ordering::Relaxed)
+        }
+
+        /// The longest any single caller has waited to write the list, in microseconds.
+        pub fn max_write_wait_us(&self) -> u64 { self.max_write_wait_us.load(Ordering::Relaxed) }
+
+        fn record_read_wait(&self, started: Instant) {
+            self.total_read_wait_us
+                .fetch_add(micros_since(started), Ordering::Relaxed);
+        }
+
+        fn record_write_wait(&self, started: Instant) {
+            let wait_us = micros_since(started);
+            self.total_write_wait_us
+                .fetch_add(wait_us, Ordering::Relaxed);
+            let mut max = self.max_write_wait_us.load(Ordering::Relaxed);
+            while wait_us > max {
+                match self.max_write_wait_us
+                          .compare_exchange_weak(max, wait_us, Ordering::Relaxed, Ordering::Relaxed)
+                {
+                    Ok(_) => break,
+                    Err(current) => max = current,
+                }
+            }
+        }
+    }
+
+    fn micros_since(started: Instant) -> u64 { started.elapsed().as_micros() as u64 }
+
     impl<T> RumorStore<T> {
         pub fn get_update_counter(&self) -> usize { self.update_counter.load(Ordering::Relaxed) }
 
@@ -287,6 +338,26 @@ mod storage {
         /// which it will be.
         fn increment_update_counter(&self) { self.update_counter.fetch_add(1, Ordering::Relaxed); }
 
+        pub fn lock_metrics(&self) -> &RumorStoreLockMetrics { &self.lock_metrics }
+
+        /// # Locking (see locking.md)
+        /// * `RumorStore::list` (read)
+        fn read_list(&self) -> ReadGuard<RumorMap<T>> {
+            let started = Instant::now();
+            let list = self.list.read();
+            self.lock_metrics.record_read_wait(started);
+            list
+        }
+
+        /// # Locking (see locking.md)
+        /// * `RumorStore::list` (write)
+        fn write_list(&self) -> WriteGuard<RumorMap<T>> {
+            let started = Instant::now();
+            let list = self.list.write();
+            self.lock_metrics.record_write_wait(started);
+            list
+        }
+
         /// # Locking (see locking.md)
         /// * `RumorStore::list` (read)
         /// * IterableGuard contains an instance of a held lock in order to facilitate ergonomic
@@ -294,12 +365,34 @@ mod storage {
         ///   held until the `IterableGuard` goes out of scope. In general, it's best to avoid
         ///   binding the return of `lock_rsr` in favor of using it as the first link in a chain of
         ///   functions that will be consumed by an iterator adapter or `for` loop.
-        pub fn lock_rsr(&self) -> IterableGuard<RumorMap<T>> { IterableGuard::read(&self.list) }
+        pub fn lock_rsr(&self) -> IterableGuard<RumorMap<T>> { IterableGuard::read(self) }
+
+        /// Whether the store has a rumor from `member_id` for `service_group`.
+        ///
+        /// # Locking (see locking.md)
+        /// * `RumorStore::list` (read)
+        pub fn contains_rsr(&self, member_id: &str, service_group: &str) -> bool {
+            self.lock_rsr()
+                .service_group(service_group)
+                .contains_id(member_id)
+        }
+
+        /// A copy of the rumor from `member_id` for `service_group`, if the store has one.
+        ///
+        /// # Locking (see locking.md)
+        /// * `RumorStore::list` (read)
+        pub fn get_rsr(&self, member_id: &str, service_group: &str) -> Option<T>
+            where T: Clone
+        {
+            self.lock_rsr()
+                .service_group(service_group)
+                .map_rumor(member_id, T::clone)
+        }
 
         /// # Locking (see locking.md)
         /// * `RumorStore::list` (write)
         pub fn remove_rsw(&self, key: &str, id: &str) {
-            let mut list = self.list.write();
+            let mut list = self.write_list();
             list.get_mut(key).and_then(|r| r.remove(id));
         }
     }
@@ -311,7 +404,7 @@ mod storage {
         /// # Locking (see locking.md)
         /// * `RumorStore::list` (write)
         pub fn insert_rsw(&self, rumor: R) -> bool {
-            let mut list = self.list.write();
+            let mut list = self.write_list();
             let rumors = list.entry(String::from(rumor.key()))
                              .or_insert_with(HashMap::new);
             let kind_ignored_count =
@@ -338,7 +431,8 @@ mod storage {
     impl<T> Default for RumorStore<T> {
         fn default() -> RumorStore<T> {
             RumorStore { list:           Arc::default(),
-                         update_counter: Arc::default(), }
+                         update_counter: Arc::default(),
+                         lock_metrics:   Arc::default(), }
         }
     }
 
@@ -350,7 +444,7 @@ mod storage {
             where S: Serializer
         {
             let mut strukt = serializer.serialize_struct("rumor_store", 2)?;
-            strukt.serialize_field("list", &*(self.list.read()))?;
+            strukt.serialize_field("list", &*(self.read_list()))?;
             strukt.serialize_field("update_counter", &self.get_update_counter())?;
             strukt.end()
         }
@@ -374,7 +468,7 @@ mod storage {
         fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
             where S: Serializer
         {
-            let map = self.0.list.read();
+            let map = self.0.read_list();
             let inner_map = map.get(Departure::const_key());
             let len = if inner_map.is_some() {
                 inner_map.unwrap().len()
@@ -400,7 +494,7 @@ mod storage {
         fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
             where S: Serializer
         {
-            let map = self.0.list.read();
+            let map = self.0.read_list();
             let mut new_map = HashMap::new();
 
             for (k, v) in map.iter() {
@@ -424,7 +518,7 @@ mod storage {
         fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
             where S: Serializer
         {
-            let map = self.0.list.read();
+            let map = self.0.read_list();
             let mut m = serializer.serialize_map(Some(map.len()))?;
 
             for (key, val) in map.iter() {
@@ -441,7 +535,7 @@ mod storage {
         fn serialize<S>(&self, serializer: S) -> result::Result<S::Ok, S::Error>
             where S: Serializer
         {
-            let map = self.0.list.read();
+            let map = self.0.read_list();
             let mut m = serializer.serialize_map(Some(map.len()))?;
 
             for (key, val) in map.iter() {
@@ -474,6 +568,9 @@ mod storage {
     }
 }
 
+/// The tag of the `member` payload of the `Rumor` message in newscast.proto.
+const MEMBER_PAYLOAD_TAG: u32 = 4;
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RumorEnvelope {
     pub r#type:  RumorType,
@@ -494,7 +591,14 @@ impl RumorEnvelope {
             RumorType::ElectionUpdate => {
                 RumorKind::ElectionUpdate(ElectionUpdate::from_proto(proto)?)
             }
-            RumorType::Member => RumorKind::Membership(Membership::from_proto(proto)?),
+            RumorType::Member => {
+                let mut membership = Membership::from_proto(proto)?;
+                let payload = protocol::length_delimited_field(bytes, MEMBER_PAYLOAD_TAG)?;
+                if let Some(payload) = payload {
+                    membership.restore_unknown_fields(payload)?;
+                }
+                RumorKind::Membership(membership)
+            }
             RumorType::Service => RumorKind::Service(Box::new(Service::from_proto(proto)?)),
             RumorType::ServiceConfig => RumorKind::ServiceConfig(ServiceConfig::from_proto(proto)?),
             RumorType::ServiceFile => RumorKind::ServiceFile(ServiceFile::from_proto(proto)?),
@@ -506,10 +610,24 @@ impl RumorEnvelope {
     }
 
     pub fn encode(self) -> Result<Vec<u8>> {
-        let proto: ProtoRumor = self.into();
+        // Prost can't encode a membership's unknown fields, so that payload is appended by hand.
+        let membership = match self.kind {
+            RumorKind::Membership(ref membership) if membership.has_unknown_fields() => {
+                Some(membership.write_to_bytes()?)
+            }
+            _ => None,
+        };
+        let mut proto: ProtoRumor = self.into();
+        if membership.is_some() {
+            proto.payload = None;
+        }
         let mut buf = BytesMut::with_capacity(proto.encoded_len());
         proto.encode(&mut buf)?;
-        Ok(buf.to_vec())
+        let mut buf = buf.to_vec();
+        if let Some(membership) = membership {
+            protocol::append_length_delimited_field(&mut buf, MEMBER_PAYLOAD_TAG, &membership);
+        }
+        Ok(buf)
     }
 }
 
@@ -525,10 +643,15 @@ impl From<RumorEnvelope> for ProtoRumor {
 #[cfg(test)]
 mod tests {
     use crate::{error::Result,
+                member::{Health,
+                         Member,
+                         Membership},
                 protocol::{self,
                            newscast},
                 rumor::{Rumor,
+                        RumorEnvelope,
                         RumorKey,
+                        RumorKind,
                         RumorType}};
     use uuid::Uuid;
 
@@ -620,11 +743,37 @@ mod tests {
         assert_eq!(r.kind.to_string(), "member");
     }
 
+    #[test]
+    fn membership_unknown_fields_survive_the_rumor_envelope() {
+        let unknown_fields = vec![9 << 3, 42, 10 << 3 | 2, 3, b'n', b'e', b'w'];
+        let member = Member { unknown_fields: vec![9 << 3, 7],
+                              ..Member::default() };
+        let membership = Membership { member,
+                                      health: Health::Suspect,
+                                      unknown_fields: unknown_fields.clone() };
+        let envelope = RumorEnvelope { r#type:  RumorType::Member,
+                                       from_id: String::from("peer"),
+                                       kind:    RumorKind::Membership(membership.clone()), };
+
+        let decoded = RumorEnvelope::decode(&envelope.encode().unwrap()).unwrap();
+        assert_eq!(decoded.from_id, "peer");
+        match decoded.kind {
+            RumorKind::Membership(decoded) => {
+                assert_eq!(decoded.member, membership.member);
+                assert_eq!(decoded.health, Health::Suspect);
+                assert_eq!(decoded.unknown_fields, unknown_fields);
+            }
+            kind => panic!("decoded a {:?} rumor", kind),
+        }
+    }
+
     mod rumor_store {
         use super::*;
         use crate::{error::Error,
                     rumor::{Rumor,
                             RumorStore}};
+        use std::{thread,
+                  time::Duration};
 
         #[test]
         fn insert_adds_rumor_when_empty() {
@@ -715,5 +864,54 @@ mod tests {
               .service_group(&key)
               .map_rumor(&member_id, |o| assert_eq!(o.id, member_id));
         }
+
+        #[test]
+        fn contains_and_get_find_a_stored_rumor() {
+            let rs = RumorStore::default();
+            let f1 = FakeRumor::default();
+            rs.insert_rsw(f1.clone());
+
+            assert!(rs.contains_rsr(&f1.id, &f1.key));
+            assert_eq!(rs.get_rsr(&f1.id, &f1.key).map(|r| r.id), Some(f1.id));
+        }
+
+        #[test]
+        fn contains_and_get_miss_other_members_and_service_groups() {
+            let rs = RumorStore::default();
+            let f1 = FakeRumor::default();
+            let f2 = FakeRumor::default();
+            rs.insert_rsw(f1.clone());
+
+            assert!(!rs.contains_rsr(&f2.id, &f1.key));
+            assert!(rs.get_rsr(&f2.id, &f1.key).is_none());
+            assert!(!rs.contains_rsr(&f1.id, "unknown.default"));
+            assert!(rs.get_rsr(&f1.id, "unknown.default").is_none());
+        }
+
+        #[test]
+        fn contains_and_get_miss_a_removed_rumor() {
+            let rs = RumorStore::default();
+            let f1 = FakeRumor::default();
+            rs.insert_rsw(f1.clone());
+            rs.remove_rsw(&f1.key, &f1.id);
+
+            assert!(!rs.contains_rsr(&f1.id, &f1.key));
+            assert!(rs.get_rsr(&f1.id, &f1.key).is_none());
+        }
+
+        #[test]
+        fn lock_metrics_record_a_write_blocked_by_a_reader() {
+            let rs = RumorStore::default();
+            let guard = rs.lock_rsr();
+            let writer_rs = rs.clone();
+            let writer = thread::spawn(move || writer_rs.insert_rsw(FakeRumor::default()));
+            thread::sleep(Duration::from_millis(50));
+            drop(guard);
+            writer.join().expect("writer finished");
+
+            let metrics = rs.lock_metrics();
+            assert!(metrics.max_write_wait_us() >= 40_000);
+            assert!(metrics.total_write_wait_us() >= metrics.max_write_wait_us());
+        }
     }
 }
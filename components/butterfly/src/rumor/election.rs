@@ -22,26 +22,39 @@ use crate::{error::{Error,
                     RumorType}};
 use std::{fmt,
           ops::{Deref,
-                DerefMut}};
+                DerefMut},
+          time::Duration};
 
 pub trait ElectionRumor: ConstIdRumor {
     fn member_id(&self) -> &str;
 
     fn is_finished(&self) -> bool;
 
+    fn is_running(&self) -> bool;
+
     fn term(&self) -> u64;
+
+    fn announce_timeout(&self) -> Duration;
 }
 
 pub type Term = u64;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Election {
-    pub member_id:     String,
-    pub service_group: String,
-    pub term:          u64,
-    pub suitability:   u64,
-    pub status:        ElectionStatus,
-    pub votes:         Vec<String>,
+    pub member_id:        String,
+    pub service_group:    String,
+    pub term:             u64,
+    pub suitability:      u64,
+    pub status:           ElectionStatus,
+    pub votes:            Vec<String>,
+    /// How long members wait for a running election's candidate to finish it before they give
+    /// up on the candidate and start a new election. Zero disables the timeout.
+    #[serde(skip)]
+    pub announce_timeout: Duration,
+    /// Whether this member won the election before it was restored from the dat file, and the
+    /// ring has yet to vote for it again. This is never gossiped.
+    #[serde(skip)]
+    pub restored:         bool,
 }
 
 impl fmt::Display for Election {
@@ -73,7 +86,9 @@ impl Election {
                    } else {
                        ElectionStatus::NoQuorum
                    },
-                   votes: vec![from_id] }
+                   votes: vec![from_id],
+                   announce_timeout: Duration::from_secs(0),
+                   restored: false }
     }
 
     /// Insert a vote for the election.
@@ -94,7 +109,19 @@ impl Election {
     pub fn running(&mut self) { self.status = ElectionStatus::Running; }
 
     /// Sets the status of the election to "finished"
-    pub fn finish(&mut self) { self.status = ElectionStatus::Finished; }
+    pub fn finish(&mut self) {
+        self.status = ElectionStatus::Finished;
+        self.restored = false;
+    }
+
+    /// Reopen an election this member had won before it was restored from the dat file. Only
+    /// this member's own vote is kept, so the rest of the ring has to vote for it again before
+    /// it finishes.
+    pub fn reopen_restored(&mut self) {
+        self.status = ElectionStatus::Running;
+        self.votes = vec![self.member_id.clone()];
+        self.restored = true;
+    }
 
     /// Sets the status of the election to "NoQuorum"
     pub fn no_quorum(&mut self) { self.status = ElectionStatus::NoQuorum; }
@@ -105,7 +132,11 @@ impl ElectionRumor for Election {
 
     fn is_finished(&self) -> bool { self.status == ElectionStatus::Finished }
 
+    fn is_running(&self) -> bool { self.status == ElectionStatus::Running }
+
     fn term(&self) -> u64 { self.term }
+
+    fn announce_timeout(&self) -> Duration { self.announce_timeout }
 }
 
 impl PartialEq for Election {
@@ -131,26 +162,30 @@ impl FromProto<ProtoRumor> for Election {
             _ => panic!("from-bytes election"),
         };
         let from_id = rumor.from_id.ok_or(Error::ProtocolMismatch("from-id"))?;
-        Ok(Election { member_id:     from_id.clone(),
-                      service_group: payload.service_group
-                                            .ok_or(Error::ProtocolMismatch("service-group"))?,
-                      term:          payload.term.unwrap_or(0),
-                      suitability:   payload.suitability.unwrap_or(0),
-                      status:        payload.status
-                                            .and_then(ElectionStatus::from_i32)
-                                            .unwrap_or(ElectionStatus::Running),
-                      votes:         payload.votes, })
+        Ok(Election { member_id:        from_id.clone(),
+                      service_group:    payload.service_group
+                                               .ok_or(Error::ProtocolMismatch("service-group"))?,
+                      term:             payload.term.unwrap_or(0),
+                      suitability:      payload.suitability.unwrap_or(0),
+                      status:           payload.status
+                                               .and_then(ElectionStatus::from_i32)
+                                               .unwrap_or(ElectionStatus::Running),
+                      votes:            payload.votes,
+                      announce_timeout: Duration::from_millis(payload.announce_timeout_ms
+                                                                     .unwrap_or(0)),
+                      restored:         false, })
     }
 }
 
 impl From<Election> for newscast::Election {
     fn from(value: Election) -> Self {
-        newscast::Election { member_id:     Some(value.member_id),
-                             service_group: Some(value.service_group.to_string()),
-                             term:          Some(value.term),
-                             suitability:   Some(value.suitability),
-                             status:        Some(value.status as i32),
-                             votes:         value.votes, }
+        newscast::Election { member_id:           Some(value.member_id),
+                             service_group:       Some(value.service_group.to_string()),
+                             term:                Some(value.term),
+                             suitability:         Some(value.suitability),
+                             status:              Some(value.status as i32),
+                             votes:               value.votes,
+                             announce_timeout_ms: Some(value.announce_timeout.as_millis() as u64), }
     }
 }
 
@@ -161,6 +196,9 @@ impl Rumor for Election {
         debug!(" with received {:?}", other);
         if *self == other {
             debug!("stored and received rumors are equal; nothing to do");
+            // Equality leaves out the restored marker, so carry it over from a restored copy of
+            // the stored election, such as one reopened by `reopen_restored`.
+            self.restored |= other.restored;
             false
         } else if other.term >= self.term && other.status == ElectionStatus::Finished {
             debug!("received is finished and represents a newer term; replace stored and share");
@@ -240,7 +278,11 @@ impl ElectionRumor for ElectionUpdate {
 
     fn is_finished(&self) -> bool { self.status == ElectionStatus::Finished }
 
+    fn is_running(&self) -> bool { self.status == ElectionStatus::Running }
+
     fn term(&self) -> u64 { self.term }
+
+    fn announce_timeout(&self) -> Duration { self.announce_timeout }
 }
 
 impl Deref for ElectionUpdate {
@@ -288,6 +330,7 @@ impl ConstIdRumor for ElectionUpdate {
 #[cfg(test)]
 mod tests {
     use crate::rumor::{election::{Election,
+                                  ElectionRumor as _,
                                   ElectionUpdate,
                                   Term},
                        ConstIdRumor as _,
@@ -315,6 +358,25 @@ mod tests {
                             true /* has_quorum */)
     }
 
+    #[test]
+    fn a_restored_election_is_reopened_until_it_finishes_again() {
+        let mut election = create_election("member_1", 1);
+        election.insert_vote("member_2");
+        election.finish();
+
+        election.reopen_restored();
+        assert!(election.is_running());
+        assert!(election.restored);
+        assert_eq!(election.votes, vec!["member_1".to_string()]);
+
+        let mut stored = create_election("member_1", 1);
+        assert!(!stored.merge(election.clone()));
+        assert!(stored.restored);
+
+        election.finish();
+        assert!(!election.restored);
+    }
+
     #[test]
     fn only_the_latest_election_is_kept() {
         let rs = create_election_rumor_store();
@@ -98,6 +98,15 @@ pub(crate) mod sync {
             self.0.insert(rumor.into(), Default::default());
         }
 
+        /// Stop tracking a rumor that has been removed from its store, so it is no longer offered
+        /// to anyone.
+        ///
+        /// # Locking (see locking.md)
+        /// * `RumorHeat::inner` (write)
+        pub fn stop_hot_rumor<T: Into<RumorKey>>(&mut self, rumor: T) {
+            self.0.remove(&rumor.into());
+        }
+
         /// For each rumor given, "cool" the rumor for the given member by
         /// incrementing the count for how many times it has been sent
         /// out. As a rumor cools, it will eventually cross a threshold
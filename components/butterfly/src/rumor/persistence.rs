@@ -0,0 +1,179 @@
+//! Where a `Server` keeps its rumors between runs.
+//!
+//! A server with a data path keeps them in a dat file (see `dat_file::DatFilePersistence`)
+//! unless another `RumorPersistence` is plugged in with `Server::set_persistence`.
+
+use crate::{error::Result,
+            member::{MemberList,
+                     Membership},
+            rumor::{Departure,
+                    Election,
+                    ElectionUpdate,
+                    Rumor,
+                    RumorStore,
+                    Service,
+                    ServiceConfig,
+                    ServiceFile},
+            server::Server};
+use std::{fmt,
+          io,
+          sync::Mutex};
+
+/// A backend that a server's rumors are snapshotted to, and restored from when it starts.
+///
+/// The `Display` implementation says where the snapshot is kept, for logging.
+pub trait RumorPersistence: fmt::Debug + fmt::Display + Send + Sync {
+    /// Insert every rumor in the last snapshot into `server`. This is called once, as the server
+    /// starts.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `MemberList::entries` (write)
+    /// * `RumorHeat::inner` (write)
+    /// * `ManagerServices::inner` (read)
+    fn read_snapshot_rsw_mlw_rhw_msr(&self, server: &Server) -> Result<()>;
+
+    /// Replace the last snapshot with `snapshot`. This is called from the persist thread with a
+    /// copy of the server's rumors, so no locks are held however long it takes.
+    fn write_snapshot(&self, snapshot: &RumorSnapshot) -> Result<()>;
+
+    /// Describe the snapshot for `Server::dump_diagnostics_rsr_mlr_smr`.
+    fn write_diagnostics(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        writeln!(writer, "location: {}", self)
+    }
+}
+
+/// Keeps the snapshot in memory, so it only lasts as long as the process. This lets tests
+/// exercise persistence without touching the filesystem.
+#[derive(Debug, Default)]
+pub struct MemoryPersistence {
+    snapshot: Mutex<RumorSnapshot>,
+}
+
+/// A copy of every member and rumor a server holds at one moment. Taking one only holds each
+/// store's read lock for as long as it takes to clone its rumors, so encoding and writing them
+/// out, which can take much longer, never blocks gossip from inserting new ones.
+#[derive(Clone, Debug, Default)]
+pub struct RumorSnapshot {
+    pub members:          Vec<Membership>,
+    pub services:         Vec<Service>,
+    pub service_configs:  Vec<ServiceConfig>,
+    pub service_files:    Vec<ServiceFile>,
+    pub elections:        Vec<Election>,
+    pub election_updates: Vec<ElectionUpdate>,
+    pub departures:       Vec<Departure>,
+}
+
+impl RumorSnapshot {
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    pub fn take_rsr_mlr(server: &Server) -> Result<Self> {
+        Self::from_stores_rsr_mlr(&server.member_list,
+                                  &server.service_store,
+                                  &server.service_config_store,
+                                  &server.service_file_store,
+                                  &server.election_store,
+                                  &server.update_store,
+                                  &server.departure_store)
+    }
+
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    pub fn from_stores_rsr_mlr(member_list: &MemberList,
+                               service_store: &RumorStore<Service>,
+                               service_config_store: &RumorStore<ServiceConfig>,
+                               service_file_store: &RumorStore<ServiceFile>,
+                               election_store: &RumorStore<Election>,
+                               update_store: &RumorStore<ElectionUpdate>,
+                               departure_store: &RumorStore<Departure>)
+                               -> Result<Self> {
+        let mut members = Vec::new();
+        member_list.with_memberships_mlr(|membership| {
+                       members.push(membership);
+                       Ok(())
+                   })?;
+        Ok(RumorSnapshot { members,
+                           services: rumors_rsr(service_store),
+                           service_configs: rumors_rsr(service_config_store),
+                           service_files: rumors_rsr(service_file_store),
+                           elections: rumors_rsr(election_store),
+                           election_updates: rumors_rsr(update_store),
+                           departures: rumors_rsr(departure_store) })
+    }
+
+    /// How many members and rumors the snapshot holds.
+    pub fn len(&self) -> usize {
+        self.members.len()
+        + self.services.len()
+        + self.service_configs.len()
+        + self.service_files.len()
+        + self.elections.len()
+        + self.election_updates.len()
+        + self.departures.len()
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl MemoryPersistence {
+    /// How many members and rumors the last snapshot holds.
+    pub fn len(&self) -> usize {
+        self.snapshot
+            .lock()
+            .expect("MemoryPersistence lock poisoned")
+            .len()
+    }
+
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl RumorPersistence for MemoryPersistence {
+    fn read_snapshot_rsw_mlw_rhw_msr(&self, server: &Server) -> Result<()> {
+        // Work from a copy, so the snapshot isn't locked while the server's locks are taken
+        let snapshot = self.snapshot
+                           .lock()
+                           .expect("MemoryPersistence lock poisoned")
+                           .clone();
+        for Membership { member, health, .. } in snapshot.members {
+            server.insert_member_mlw_rhw(member, health);
+        }
+        for service in snapshot.services {
+            server.insert_service_rsw_mlw_rhw(service);
+        }
+        for service_config in snapshot.service_configs {
+            server.insert_service_config_rsw_rhw(service_config);
+        }
+        for service_file in snapshot.service_files {
+            server.insert_service_file_rsw_rhw(service_file);
+        }
+        for election in snapshot.elections {
+            server.restore_election_rsw_mlr_rhw_msr(election);
+        }
+        for update_election in snapshot.election_updates {
+            server.restore_update_election_rsw_mlr_rhw(update_election);
+        }
+        for departure in snapshot.departures {
+            server.insert_departure_rsw_mlw_rhw(departure);
+        }
+        Ok(())
+    }
+
+    fn write_snapshot(&self, snapshot: &RumorSnapshot) -> Result<()> {
+        *self.snapshot
+             .lock()
+             .expect("MemoryPersistence lock poisoned") = snapshot.clone();
+        Ok(())
+    }
+}
+
+impl fmt::Display for MemoryPersistence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "memory") }
+}
+
+/// # Locking (see locking.md)
+/// * `RumorStore::list` (read)
+fn rumors_rsr<T: Rumor>(store: &RumorStore<T>) -> Vec<T> {
+    store.lock_rsr().rumors().cloned().collect()
+}
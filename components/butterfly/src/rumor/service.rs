@@ -6,7 +6,8 @@ use crate::{error::{Error,
                     Result},
             protocol::{self,
                        newscast,
-                       FromProto},
+                       FromProto,
+                       Message},
             rumor::{Rumor,
                     RumorPayload,
                     RumorType}};
@@ -109,6 +110,22 @@ impl Service {
                           })
                           .unwrap_or_default() }
     }
+
+    /// Checks the fields that a malformed rumor from the network could have left empty. The
+    /// service group needs no check here, since it can't be parsed from an empty string.
+    ///
+    /// # Errors
+    /// * Error::InvalidRumor if the member ID is empty or the incarnation is 0
+    pub fn validate(&self) -> Result<()> {
+        if self.member_id.is_empty() {
+            return Err(Error::InvalidRumor(Self::MESSAGE_ID, "the member ID is empty"));
+        }
+        // The Supervisor gossips its services from incarnation 1
+        if self.incarnation == 0 {
+            return Err(Error::InvalidRumor(Self::MESSAGE_ID, "the incarnation is 0"));
+        }
+        Ok(())
+    }
 }
 
 impl protocol::Message<newscast::Rumor> for Service {
@@ -230,8 +247,9 @@ mod tests {
                        service::ServiceGroup};
 
     use super::Service;
-    use crate::rumor::{service::SysInfo,
-                       Rumor};
+    use crate::{error::Error,
+                rumor::{service::SysInfo,
+                        Rumor}};
 
     fn create_service(member_id: &str) -> Service {
         let pkg = PackageIdent::from_str("core/neurosis/1.2.3/20161208121212").unwrap();
@@ -317,6 +335,36 @@ mod tests {
         assert_eq!(s1, s1_check);
     }
 
+    #[test]
+    fn services_from_incarnation_1_with_a_member_id_are_valid() {
+        let mut service = create_service("adam");
+        service.incarnation = 1;
+        assert!(service.validate().is_ok());
+    }
+
+    #[test]
+    fn services_without_a_member_id_are_invalid() {
+        let mut service = create_service("");
+        service.incarnation = 1;
+        match service.validate() {
+            Err(Error::InvalidRumor("Service", reason)) => {
+                assert_eq!(reason, "the member ID is empty")
+            }
+            other => panic!("Expected InvalidRumor, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn services_at_incarnation_0_are_invalid() {
+        let service = create_service("adam");
+        match service.validate() {
+            Err(Error::InvalidRumor("Service", reason)) => {
+                assert_eq!(reason, "the incarnation is 0")
+            }
+            other => panic!("Expected InvalidRumor, got {:?}", other),
+        }
+    }
+
     #[test]
     #[should_panic]
     fn service_package_name_mismatch() {
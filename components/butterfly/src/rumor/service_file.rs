@@ -1,6 +1,10 @@
 //! The ServiceFile rumor.
 //!
 //! Holds the toml configuration injected for a service.
+//!
+//! Once chunking is turned on with `ServiceFileChunkBytes`, a file larger than it is gossiped as a
+//! set of chunk rumors rather than in one piece (see `ServiceFile::into_chunks`), and is put back
+//! together with `reassemble` once every chunk has arrived.
 
 use crate::{error::{Error,
                     Result},
@@ -11,10 +15,12 @@ use crate::{error::{Error,
             rumor::{Rumor,
                     RumorPayload,
                     RumorType}};
-use habitat_core::{crypto::{keys::box_key_pair::WrappedSealedBox,
+use habitat_core::{crypto::{hash,
+                            keys::box_key_pair::WrappedSealedBox,
                             BoxKeyPair},
                    service::ServiceGroup};
 use std::{cmp::Ordering,
+          collections::HashMap,
           fmt,
           mem,
           path::Path,
@@ -28,6 +34,33 @@ pub struct ServiceFile {
     pub encrypted:     bool,
     pub filename:      String,
     pub body:          Vec<u8>, // TODO: make this a String
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub chunk:         Option<Chunk>,
+    /// The rumor id: the filename, with the chunk index appended for a chunk, so that each chunk
+    /// of a file is stored and gossiped separately.
+    #[serde(skip)]
+    id:                String,
+}
+
+/// Where a chunk rumor's body belongs in the file it was split from.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Chunk {
+    pub index:          u32,
+    pub total:          u32,
+    /// The length of the whole file.
+    pub content_length: u64,
+    /// The `hash_bytes` of the whole file, checked once its chunks are put back together.
+    pub content_hash:   String,
+}
+
+/// Everything the chunks of one version of a file have in common, which `reassemble` groups
+/// them by.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChunkSet {
+    pub service_group: String,
+    pub filename:      String,
+    pub incarnation:   u64,
+    pub content_hash:  String,
 }
 
 impl fmt::Display for ServiceFile {
@@ -55,6 +88,7 @@ impl PartialEq for ServiceFile {
         && self.encrypted == other.encrypted
         && self.filename == other.filename
         && self.body == other.body
+        && self.chunk == other.chunk
     }
 }
 
@@ -68,12 +102,84 @@ impl ServiceFile {
         where S1: Into<String>,
               S2: Into<String>
     {
+        let filename = filename.into();
         ServiceFile { from_id: member_id.into(),
                       service_group,
                       incarnation: 0,
                       encrypted: false,
-                      filename: filename.into(),
-                      body }
+                      id: filename.clone(),
+                      filename,
+                      body,
+                      chunk: None }
+    }
+
+    /// Split the file into rumors of at most `chunk_size` bytes each, or return it as it is if it
+    /// is small enough already or `chunk_size` is zero. Encrypt the file first: the chunks are put
+    /// back together before they are decrypted.
+    ///
+    /// # Errors
+    /// * Error::ServiceFileTooLarge if the file is larger than `max_size`
+    pub fn into_chunks(self, max_size: usize, chunk_size: usize) -> Result<Vec<ServiceFile>> {
+        self.validate_size(max_size)?;
+        if chunk_size == 0 || self.body.len() <= chunk_size {
+            return Ok(vec![self]);
+        }
+
+        let pieces = self.body.chunks(chunk_size).collect::<Vec<_>>();
+        let content_length = self.body.len() as u64;
+        let content_hash = hash::hash_bytes(&self.body);
+        let chunks = pieces.iter()
+                           .enumerate()
+                           .map(|(index, piece)| {
+                               let chunk = Chunk { index: index as u32,
+                                                   total: pieces.len() as u32,
+                                                   content_length,
+                                                   content_hash: content_hash.clone() };
+                               ServiceFile { from_id:       self.from_id.clone(),
+                                             service_group: self.service_group.clone(),
+                                             incarnation:   self.incarnation,
+                                             encrypted:     self.encrypted,
+                                             filename:      self.filename.clone(),
+                                             body:          piece.to_vec(),
+                                             id:            Self::chunk_id(&self.filename, &chunk),
+                                             chunk:         Some(chunk), }
+                           })
+                           .collect();
+        Ok(chunks)
+    }
+
+    fn chunk_id(filename: &str, chunk: &Chunk) -> String { format!("{}#{}", filename, chunk.index) }
+
+    /// Check that the whole file, even if this rumor only carries one chunk of it, is no larger
+    /// than `max_bytes`.
+    ///
+    /// # Errors
+    /// * Error::ServiceFileTooLarge if the file is larger than `max_bytes`
+    pub fn validate_size(&self, max_bytes: usize) -> Result<()> {
+        let size = self.content_length();
+        if size > max_bytes as u64 {
+            return Err(Error::ServiceFileTooLarge { filename: self.filename.clone(),
+                                                    size:     size as usize,
+                                                    limit:    max_bytes, });
+        }
+        Ok(())
+    }
+
+    /// The size of the whole file, even if this rumor only carries one chunk of it.
+    pub fn content_length(&self) -> u64 {
+        self.chunk
+            .as_ref()
+            .map_or(self.body.len() as u64, |chunk| chunk.content_length)
+    }
+
+    /// The set this rumor belongs to, if it is a chunk.
+    pub fn chunk_set(&self) -> Option<ChunkSet> {
+        self.chunk.as_ref().map(|chunk| {
+                               ChunkSet { service_group: self.service_group.to_string(),
+                                          filename:      self.filename.clone(),
+                                          incarnation:   self.incarnation,
+                                          content_hash:  chunk.content_hash.clone(), }
+                           })
     }
 
     /// Encrypt the contents of the service file
@@ -110,26 +216,50 @@ impl FromProto<ProtoRumor> for ServiceFile {
             RumorPayload::ServiceFile(payload) => payload,
             _ => panic!("from-bytes service-config"),
         };
-        Ok(ServiceFile { from_id:       rumor.from_id.ok_or(Error::ProtocolMismatch("from-id"))?,
+        let filename = payload.filename
+                              .ok_or(Error::ProtocolMismatch("filename"))?;
+        let chunk = match payload.chunk_index {
+            Some(index) => {
+                Some(Chunk { index,
+                             total: payload.chunk_total
+                                           .ok_or(Error::ProtocolMismatch("chunk-total"))?,
+                             content_length:
+                                 payload.content_length
+                                        .ok_or(Error::ProtocolMismatch("content-length"))?,
+                             content_hash:
+                                 payload.content_hash
+                                        .ok_or(Error::ProtocolMismatch("content-hash"))? })
+            }
+            None => None,
+        };
+        Ok(ServiceFile { from_id: rumor.from_id.ok_or(Error::ProtocolMismatch("from-id"))?,
                          service_group:
                              payload.service_group
                                     .ok_or(Error::ProtocolMismatch("service-group"))
                                     .and_then(|s| ServiceGroup::from_str(&s).map_err(Error::from))?,
-                         incarnation:   payload.incarnation.unwrap_or(0),
-                         encrypted:     payload.encrypted.unwrap_or(false),
-                         filename:      payload.filename
-                                               .ok_or(Error::ProtocolMismatch("filename"))?,
-                         body:          payload.body.unwrap_or_default(), })
+                         incarnation: payload.incarnation.unwrap_or(0),
+                         encrypted: payload.encrypted.unwrap_or(false),
+                         id: chunk.as_ref()
+                                  .map_or_else(|| filename.clone(),
+                                               |chunk| Self::chunk_id(&filename, chunk)),
+                         filename,
+                         body: payload.body.unwrap_or_default(),
+                         chunk })
     }
 }
 
 impl From<ServiceFile> for newscast::ServiceFile {
     fn from(value: ServiceFile) -> Self {
-        newscast::ServiceFile { service_group: Some(value.service_group.to_string()),
-                                incarnation:   Some(value.incarnation),
-                                encrypted:     Some(value.encrypted),
-                                filename:      Some(value.filename),
-                                body:          Some(value.body), }
+        let chunk = value.chunk;
+        newscast::ServiceFile { service_group:  Some(value.service_group.to_string()),
+                                incarnation:    Some(value.incarnation),
+                                encrypted:      Some(value.encrypted),
+                                filename:       Some(value.filename),
+                                body:           Some(value.body),
+                                chunk_index:    chunk.as_ref().map(|chunk| chunk.index),
+                                chunk_total:    chunk.as_ref().map(|chunk| chunk.total),
+                                content_length: chunk.as_ref().map(|chunk| chunk.content_length),
+                                content_hash:   chunk.map(|chunk| chunk.content_hash), }
     }
 }
 
@@ -147,11 +277,60 @@ impl Rumor for ServiceFile {
 
     fn kind(&self) -> RumorType { RumorType::ServiceFile }
 
-    fn id(&self) -> &str { &self.filename }
+    fn id(&self) -> &str { &self.id }
 
     fn key(&self) -> &str { &self.service_group }
 }
 
+/// Put chunked files back together. Files that were never chunked are returned as they are, and
+/// chunks whose set is still missing some of its members are left out until the rest arrive.
+///
+/// # Errors
+/// * Error::ServiceFileHashMismatch for a complete set whose chunks don't add up to the file they
+///   were split from
+pub fn reassemble<'a, I>(rumors: I) -> Vec<Result<ServiceFile>>
+    where I: IntoIterator<Item = &'a ServiceFile>
+{
+    let mut files = Vec::new();
+    let mut sets: HashMap<ChunkSet, Vec<&ServiceFile>> = HashMap::new();
+    for rumor in rumors {
+        match rumor.chunk_set() {
+            Some(set) => sets.entry(set).or_default().push(rumor),
+            None => files.push(Ok(rumor.clone())),
+        }
+    }
+
+    for (set, mut chunks) in sets {
+        chunks.sort_by_key(|rumor| rumor.chunk.as_ref().map(|chunk| chunk.index));
+        let complete =
+            chunks.iter()
+                  .filter_map(|rumor| rumor.chunk.as_ref())
+                  .enumerate()
+                  .all(|(i, chunk)| {
+                      chunk.index as usize == i && chunk.total as usize == chunks.len()
+                  });
+        if !complete {
+            continue;
+        }
+
+        let mut file = chunks[0].clone();
+        file.body = chunks.iter()
+                          .flat_map(|rumor| rumor.body.iter().cloned())
+                          .collect();
+        if file.body.len() as u64 != file.content_length()
+           || hash::hash_bytes(&file.body) != set.content_hash
+        {
+            files.push(Err(Error::ServiceFileHashMismatch(set.service_group, set.filename)));
+            continue;
+        }
+        file.id = file.filename.clone();
+        file.chunk = None;
+        files.push(Ok(file));
+    }
+
+    files
+}
+
 #[cfg(test)]
 mod tests {
     use std::{cmp::Ordering,
@@ -159,8 +338,11 @@ mod tests {
 
     use habitat_core::service::ServiceGroup;
 
-    use super::ServiceFile;
-    use crate::rumor::Rumor;
+    use super::{reassemble,
+                ServiceFile};
+    use crate::{error::Error,
+                protocol::Message,
+                rumor::Rumor};
 
     fn create_service_file(member_id: &str, filename: &str, body: &str) -> ServiceFile {
         let body_bytes: Vec<u8> = Vec::from(body);
@@ -240,4 +422,104 @@ mod tests {
                                                                  the body"),
                    String::from("tcp-backlog = 128"));
     }
+
+    fn chunked(body: &str) -> Vec<ServiceFile> {
+        create_service_file("adam", "big.conf", body).into_chunks(1024, 4)
+                                                     .expect("service file chunked")
+    }
+
+    #[test]
+    fn into_chunks_leaves_a_file_that_fits_in_one_chunk_whole() {
+        let s1 = create_service_file("adam", "yep", "tcp");
+        assert_eq!(s1.clone().into_chunks(1024, 4).unwrap(), vec![s1]);
+    }
+
+    #[test]
+    fn into_chunks_leaves_every_file_whole_when_chunking_is_off() {
+        let s1 = create_service_file("adam", "yep", "tcp-backlog = 128");
+        assert_eq!(s1.clone().into_chunks(1024, 0).unwrap(), vec![s1]);
+    }
+
+    #[test]
+    fn validate_size_checks_the_whole_file_of_a_chunk() {
+        let s1 = create_service_file("adam", "yep", "tcp-backlog = 128");
+        assert!(s1.validate_size(17).is_ok());
+        match s1.validate_size(16) {
+            Err(Error::ServiceFileTooLarge { filename,
+                                             size: 17,
+                                             limit: 16, }) => assert_eq!(filename, "yep"),
+            other => panic!("Expected ServiceFileTooLarge, got {:?}", other),
+        }
+
+        let chunk = s1.into_chunks(1024, 4).unwrap().remove(0);
+        assert_eq!(chunk.body.len(), 4);
+        assert!(chunk.validate_size(16).is_err());
+    }
+
+    #[test]
+    fn into_chunks_rejects_a_file_over_the_limit() {
+        let s1 = create_service_file("adam", "yep", "tcp-backlog = 128");
+        match s1.into_chunks(8, 4) {
+            Err(Error::ServiceFileTooLarge { filename,
+                                             size: 17,
+                                             limit: 8, }) => assert_eq!(filename, "yep"),
+            other => panic!("Expected ServiceFileTooLarge, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chunks_are_stored_apart_and_reassemble_into_the_file() {
+        let chunks = chunked("tcp-backlog = 128");
+        assert_eq!(chunks.len(), 5);
+        let ids = chunks.iter().map(Rumor::id).collect::<Vec<_>>();
+        assert_eq!(ids,
+                   vec!["big.conf#0",
+                        "big.conf#1",
+                        "big.conf#2",
+                        "big.conf#3",
+                        "big.conf#4"]);
+
+        let files = reassemble(chunks.iter().rev()).into_iter()
+                                                   .collect::<Result<Vec<_>, _>>()
+                                                   .expect("chunks reassembled");
+
+        assert_eq!(files,
+                   vec![create_service_file("adam", "big.conf", "tcp-backlog = 128")]);
+        assert_eq!(files[0].id(), "big.conf");
+    }
+
+    #[test]
+    fn reassemble_leaves_out_a_file_until_every_chunk_has_arrived() {
+        let whole = create_service_file("adam", "yep", "tcp");
+        let mut chunks = chunked("tcp-backlog = 128");
+        chunks.remove(2);
+        chunks.push(whole.clone());
+
+        let files = reassemble(&chunks).into_iter()
+                                       .collect::<Result<Vec<_>, _>>()
+                                       .unwrap();
+
+        assert_eq!(files, vec![whole]);
+    }
+
+    #[test]
+    fn reassemble_rejects_chunks_that_do_not_match_their_hash() {
+        let mut chunks = chunked("tcp-backlog = 128");
+        chunks[1].body = b"XXXX".to_vec();
+
+        match reassemble(&chunks).as_slice() {
+            [Err(Error::ServiceFileHashMismatch(_, filename))] => assert_eq!(filename, "big.conf"),
+            other => panic!("Expected ServiceFileHashMismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chunks_keep_their_place_through_encoding() {
+        for chunk in chunked("tcp-backlog = 128") {
+            let bytes = chunk.write_to_bytes().unwrap();
+            let decoded = ServiceFile::from_bytes(&bytes).unwrap();
+            assert_eq!(decoded, chunk);
+            assert_eq!(decoded.id(), chunk.id());
+        }
+    }
 }
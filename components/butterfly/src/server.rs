@@ -21,36 +21,50 @@ use crate::{error::{Error,
                      Incarnation,
                      Member,
                      MemberList,
-                     MemberListProxy},
+                     MemberListProxy,
+                     Membership,
+                     ZONE_LABEL},
             message,
-            rumor::{dat_file::{DatFileReader,
-                               DatFileWriter},
+            protocol,
+            rumor::{dat_file::{DatFileConfig,
+                               DatFilePersistence,
+                               DatFileWal},
                     departure::Departure,
                     election::{Election,
                                ElectionRumor,
                                ElectionUpdate},
                     heat::sync::RumorHeat,
+                    persistence::{RumorPersistence,
+                                  RumorSnapshot},
                     service::Service,
                     service_config::ServiceConfig,
-                    service_file::ServiceFile,
+                    service_file::{ChunkSet,
+                                   ServiceFile},
                     ConstIdRumor,
+                    ConstKeyRumor,
                     Rumor,
                     RumorKey,
                     RumorStore,
+                    RumorStoreLockMetrics,
                     RumorStoreProxy,
                     RumorType},
-            swim::Ack};
+            swim::Ack,
+            ElectionWinnerAnnounceTimeout,
+            ServiceFileMaxBytes};
 use habitat_common::{liveliness_checker,
                      sync::Lock,
                      FeatureFlag};
-use habitat_core::crypto::SymKey;
+use habitat_core::{crypto::SymKey,
+                   env::Config};
 use prometheus::{HistogramTimer,
                  HistogramVec,
                  IntGauge};
+use prost::Message as ProstMessage;
 use serde::{ser::SerializeStruct,
             Serialize,
             Serializer};
-use std::{collections::{HashMap,
+use std::{collections::{BTreeMap,
+                        HashMap,
                         HashSet},
           fmt::{self,
                 Debug},
@@ -72,6 +86,8 @@ use std::{collections::{HashMap,
           thread,
           time::{Duration,
                  Instant}};
+use time::{Duration as TimeDuration,
+           SteadyTime};
 
 /// The maximum number of other members we should notify when we shut
 /// down and leave the ring.
@@ -96,6 +112,101 @@ impl fmt::Debug for ElectionTimer {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "An election timer!") }
 }
 
+/// The candidate a running election is waiting on to announce that it has won, and when we stop
+/// waiting for it.
+#[derive(Debug)]
+struct AnnounceDeadline {
+    term:      u64,
+    member_id: String,
+    deadline:  Instant,
+}
+
+type AnnounceDeadlines = Mutex<HashMap<RumorKey, AnnounceDeadline>>;
+
+/// How often the persist thread checks whether enough rumors have changed to write them out
+/// early, when `PersistConfig::after_changes` is set.
+const PERSIST_CHANGES_POLL_PERIOD: Duration = Duration::from_secs(1);
+
+/// When a server's rumors are persisted, trading durability against IO.
+#[derive(Clone, Debug)]
+pub struct PersistConfig {
+    /// How long the persist thread waits between writes.
+    pub interval:      Duration,
+    /// Write as soon as this many members and rumors have changed since the last write, rather
+    /// than waiting out the rest of the interval.
+    pub after_changes: Option<usize>,
+    /// Write once more as the Supervisor shuts down, such as when it is sent SIGTERM. See
+    /// `Server::persist_on_shutdown_rsr_mlr`.
+    pub on_shutdown:   bool,
+}
+
+impl Default for PersistConfig {
+    fn default() -> Self {
+        PersistConfig { interval:      Duration::from_secs(30),
+                        after_changes: None,
+                        on_shutdown:   true, }
+    }
+}
+
+impl PersistConfig {
+    /// Configure the interval from `HAB_PERSIST_LOOP_PERIOD_SECS`, 30 seconds by default, and
+    /// write early after `HAB_PERSIST_AFTER_CHANGES` changes, where 0 (the default) means only
+    /// the interval is used. An interval of 0 would persist continuously, so it is ignored in
+    /// favor of the default. Rumors are persisted on shutdown unless
+    /// `HAB_NO_PERSIST_ON_SHUTDOWN` is true.
+    pub fn from_env() -> Self {
+        habitat_core::env_config_duration!(PersistLoopPeriod,
+                                           HAB_PERSIST_LOOP_PERIOD_SECS => from_secs,
+                                           PersistConfig::default().interval);
+        habitat_core::env_config_int!(PersistAfterChanges, usize, HAB_PERSIST_AFTER_CHANGES, 0);
+        habitat_core::env_config_bool!(NoPersistOnShutdown, HAB_NO_PERSIST_ON_SHUTDOWN, false);
+        let mut interval: Duration = PersistLoopPeriod::configured_value().into();
+        if interval == Duration::from_secs(0) {
+            warn!("Ignoring HAB_PERSIST_LOOP_PERIOD_SECS=0; persisting every {}s instead",
+                  PersistConfig::default().interval.as_secs());
+            interval = PersistConfig::default().interval;
+        }
+        let after_changes: usize = PersistAfterChanges::configured_value().into();
+        let skip_shutdown: bool = NoPersistOnShutdown::configured_value().into();
+        PersistConfig { interval,
+                        after_changes: Some(after_changes).filter(|&changes| changes > 0),
+                        on_shutdown: !skip_shutdown }
+    }
+}
+
+/// How far a member's departure has spread, as counted by this server.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DeparturePropagation {
+    /// The peers this server has gossiped the departure to.
+    pub gossiped_to:  usize,
+    /// The peers that have gossiped the departure back to this server after it already knew of
+    /// it.
+    pub confirmed_by: usize,
+}
+
+/// The peers behind a `DeparturePropagation`'s counts, so that each is only counted once.
+#[derive(Debug)]
+struct DepartureWitnesses {
+    gossiped_to:  HashSet<String>,
+    confirmed_by: HashSet<String>,
+    /// When the first peer was counted. The witnesses are purged once this is older than
+    /// `Timing::departure_witness_timeout_duration`.
+    first_seen:   SteadyTime,
+}
+
+impl DepartureWitnesses {
+    fn new() -> Self {
+        DepartureWitnesses { gossiped_to:  HashSet::new(),
+                             confirmed_by: HashSet::new(),
+                             first_seen:   SteadyTime::now(), }
+    }
+
+    fn propagation(&self) -> DeparturePropagation {
+        DeparturePropagation { gossiped_to:  self.gossiped_to.len(),
+                               confirmed_by: self.confirmed_by.len(), }
+    }
+}
+
 type AckReceiver = mpsc::Receiver<(SocketAddr, Ack)>;
 type AckSender = mpsc::Sender<(SocketAddr, Ack)>;
 
@@ -288,7 +399,17 @@ pub struct Server {
     gossip_addr:              SocketAddr,
     suitability_lookup:       Arc<dyn Suitability>,
     data_path:                Option<PathBuf>,
-    dat_file:                 Option<Arc<Mutex<DatFileWriter>>>,
+    persistence:              Option<Arc<dyn RumorPersistence>>,
+    dat_file_wal:             Option<Arc<DatFileWal>>,
+    persist_config:           PersistConfig,
+    service_file_limit:       usize,
+    /// When the first chunk of each set of service file chunks arrived, so that sets which are
+    /// never completed can be purged.
+    service_file_chunks:      Arc<Mutex<HashMap<ChunkSet, SteadyTime>>>,
+    /// Who each departure has been gossiped to and confirmed by, by departed member id.
+    departure_witnesses:      Arc<Mutex<HashMap<String, DepartureWitnesses>>>,
+    announce_timeout:         Duration,
+    announce_deadlines:       Arc<AnnounceDeadlines>,
     socket:                   Option<UdpSocket>,
     departed:                 Arc<AtomicBool>,
     // These are all here for testing support
@@ -297,6 +418,7 @@ pub struct Server {
     gossip_rounds:   Arc<AtomicIsize>,
     block_list:      Arc<Lock<HashSet<String>>>,
     election_timers: Arc<Mutex<HashMap<String, ElectionTimer>>>,
+    start_time:      Instant,
 }
 
 impl Clone for Server {
@@ -317,14 +439,22 @@ impl Clone for Server {
                  gossip_addr:          self.gossip_addr,
                  suitability_lookup:   self.suitability_lookup.clone(),
                  data_path:            self.data_path.clone(),
-                 dat_file:             self.dat_file.clone(),
+                 persistence:          self.persistence.clone(),
+                 dat_file_wal:         self.dat_file_wal.clone(),
+                 persist_config:       self.persist_config.clone(),
+                 service_file_limit:   self.service_file_limit,
+                 service_file_chunks:  self.service_file_chunks.clone(),
+                 departure_witnesses:  self.departure_witnesses.clone(),
+                 announce_timeout:     self.announce_timeout,
+                 announce_deadlines:   self.announce_deadlines.clone(),
                  departed:             self.departed.clone(),
                  pause:                self.pause.clone(),
                  swim_rounds:          self.swim_rounds.clone(),
                  gossip_rounds:        self.gossip_rounds.clone(),
                  block_list:           self.block_list.clone(),
                  socket:               None,
-                 election_timers:      self.election_timers.clone(), }
+                 election_timers:      self.election_timers.clone(),
+                 start_time:           self.start_time, }
     }
 }
 
@@ -379,14 +509,23 @@ impl Server {
                             gossip_addr: gossip_socket_addr,
                             suitability_lookup,
                             data_path: data_path.as_ref().map(|p| p.into()),
-                            dat_file: None,
+                            persistence: None,
+                            dat_file_wal: None,
+                            persist_config: PersistConfig::from_env(),
+                            service_file_limit: ServiceFileMaxBytes::configured_value().into(),
+                            service_file_chunks: Arc::new(Mutex::new(HashMap::new())),
+                            departure_witnesses: Arc::new(Mutex::new(HashMap::new())),
+                            announce_timeout:
+                                ElectionWinnerAnnounceTimeout::configured_value().into(),
+                            announce_deadlines: Arc::new(Mutex::new(HashMap::new())),
                             departed: Arc::new(AtomicBool::new(false)),
                             pause: Arc::new(AtomicBool::new(false)),
                             swim_rounds: Arc::new(AtomicIsize::new(0)),
                             gossip_rounds: Arc::new(AtomicIsize::new(0)),
                             block_list: Arc::new(Lock::new(HashSet::new())),
                             socket: None,
-                            election_timers: Arc::new(Mutex::new(HashMap::new())) })
+                            election_timers: Arc::new(Mutex::new(HashMap::new())),
+                            start_time: Instant::now() })
             }
             (Err(e), _) | (_, Err(e)) => Err(Error::CannotBind(e)),
             (Ok(None), _) | (_, Ok(None)) => {
@@ -396,6 +535,34 @@ impl Server {
         }
     }
 
+    /// Persist rumors to `persistence` rather than to a dat file in the data path. This must be
+    /// called before the server starts, which is when the rumors persisted last time are read
+    /// back.
+    pub fn set_persistence(&mut self, persistence: Arc<dyn RumorPersistence>) {
+        self.persistence = Some(persistence);
+    }
+
+    /// Configure when rumors are persisted, in place of `PersistConfig::from_env`. This must be
+    /// called before the server starts the persist thread.
+    pub fn set_persist_config(&mut self, persist_config: PersistConfig) {
+        self.persist_config = persist_config;
+    }
+
+    /// How many times the member list and rumor stores have changed. This only ever grows
+    /// (wrapping on overflow), so the difference between two readings counts the changes between
+    /// them.
+    fn update_counter(&self) -> usize {
+        let counters = [self.member_list.get_update_counter(),
+                        self.service_store.get_update_counter(),
+                        self.service_config_store.get_update_counter(),
+                        self.service_file_store.get_update_counter(),
+                        self.election_store.get_update_counter(),
+                        self.update_store.get_update_counter(),
+                        self.departure_store.get_update_counter()];
+        counters.iter()
+                .fold(0, |sum, &counter| sum.wrapping_add(counter))
+    }
+
     /// Every iteration of the outbound protocol (which means every member has been pinged if they
     /// are available) increments the round. If we exceed an isize in rounds, we reset to 0.
     ///
@@ -458,41 +625,42 @@ impl Server {
     pub fn start_rsw_mlw_smw_rhw_msr(&mut self, timing: &timing::Timing) -> Result<()> {
         debug!("entering habitat_butterfly::server::Server::start");
         let (tx_outbound, rx_inbound) = channel();
+        let mut dat_file_wal = None;
         if let Some(ref path) = self.data_path {
             if let Some(err) = fs::create_dir_all(path).err() {
                 return Err(Error::BadDataPath(path.to_path_buf(), err));
             }
 
-            let dat_path = path.join(format!("{}.rst", &self.member_id));
-            let mut reader = DatFileReader::read_or_create_rsr_mlr(dat_path.clone(),
-                                                                   &self.member_list,
-                                                                   &self.service_store,
-                                                                   &self.service_config_store,
-                                                                   &self.service_file_store,
-                                                                   &self.election_store,
-                                                                   &self.update_store,
-                                                                   &self.departure_store)?;
-
-            match reader.read_into_rsw_mlw_rhw_msr(&self) {
-                Ok(_) => {
-                    debug!("Successfully ingested rumors from {}",
-                           reader.path().display())
+            if self.persistence.is_none() {
+                let dat_path = path.join(format!("{}.rst", &self.member_id));
+                let config = DatFileConfig::from_env();
+                config.validate()?;
+                if config.use_wal {
+                    let mut wal = DatFileWal::open(&dat_path)?;
+                    wal.set_compact_size(config.compact_size);
+                    dat_file_wal = Some(Arc::new(wal));
                 }
-                Err(Error::DatFileIO(path, err)) => error!("{}", Error::DatFileIO(path, err)),
-                Err(err) => return Err(err),
-            };
+                let persistence = DatFilePersistence::new(dat_path,
+                                                          config,
+                                                          self.member_id(),
+                                                          (*self.ring_key).clone());
+                self.persistence = Some(Arc::new(persistence));
+            }
+        }
 
-            let writer = DatFileWriter::new(dat_path);
-            self.dat_file = Some(Arc::new(Mutex::new(writer)));
+        if let Some(persistence) = self.persistence.clone() {
+            persistence.read_snapshot_rsw_mlw_rhw_msr(self)?;
+        }
+        // Only rumors inserted from here on are logged; those just read are already persisted.
+        self.dat_file_wal = dat_file_wal;
 
-            {
-                // Set up the incarnation persistence and ensure that
-                // our Member is synced with whatever has been
-                // persisted previously.
-                let mut store = incarnation_store::IncarnationStore::new(path.join("INCARNATION"));
-                store.initialize()?;
-                self.myself.lock_smw().sync_incarnation(store)?;
-            }
+        if let Some(ref path) = self.data_path {
+            // Set up the incarnation persistence and ensure that
+            // our Member is synced with whatever has been
+            // persisted previously.
+            let mut store = incarnation_store::IncarnationStore::new(path.join("INCARNATION"));
+            store.initialize()?;
+            self.myself.lock_smw().sync_incarnation(store)?;
         }
 
         let socket = UdpSocket::bind(self.swim_addr)?;
@@ -524,7 +692,7 @@ impl Server {
                            self.clone(),
                            timing.clone())?;
 
-        if self.dat_file.is_some() {
+        if self.persistence.is_some() {
             spawn_persist_thread(format!("persist-{}", self.name()), self.clone())?;
         }
 
@@ -536,6 +704,48 @@ impl Server {
     ///   the closure must not call any functions which take this lock.
     pub fn need_peer_seeding_mlr(&self) -> bool { self.member_list.is_empty_mlr() }
 
+    /// A snapshot of the members whose health is `health`.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (read)
+    pub fn peers_by_health_mlr(&self, health: Health) -> Vec<Member> {
+        self.member_list.members_with_health_mlr(health)
+    }
+
+    /// The members to send rumors to this gossip round: every other member, in random order.
+    ///
+    /// If we have a zone label, the order is biased by zone. The push thread sends to peers in
+    /// batches of `push::FANOUT`, and each batch takes up to `push::FANOUT / 2` peers from our
+    /// own zone and fills the rest from other zones, topping up from our own zone once they run
+    /// out. Every member is still returned, so zones don't change how many peers are gossiped to.
+    ///
+    /// # Locking (see locking.md)
+    /// * `Server::member` (read)
+    /// * `MemberList::entries` (read)
+    pub fn gossip_peers_for_round_mlr_smr(&self) -> Vec<Member> {
+        let check_list = self.member_list.check_list_mlr(self.member_id());
+        let zone = match self.myself.lock_smr().to_member().labels.remove(ZONE_LABEL) {
+            Some(zone) => zone,
+            None => return check_list,
+        };
+        let peer_count = check_list.len();
+        let (same_zone, other_zones): (Vec<_>, Vec<_>) =
+            check_list.into_iter()
+                      .partition(|member| member.labels.get(ZONE_LABEL) == Some(&zone));
+        let mut same_zone = same_zone.into_iter();
+        let mut other_zones = other_zones.into_iter();
+        let mut peers = Vec::with_capacity(peer_count);
+        while peers.len() < peer_count {
+            let batch_start = peers.len();
+            peers.extend(same_zone.by_ref().take(push::FANOUT / 2));
+            let other_zone_slots = push::FANOUT - (peers.len() - batch_start);
+            peers.extend(other_zones.by_ref().take(other_zone_slots));
+            let remaining_slots = push::FANOUT - (peers.len() - batch_start);
+            peers.extend(same_zone.by_ref().take(remaining_slots));
+        }
+        peers
+    }
+
     /// Persistently block a given address, causing no traffic to be seen.
     ///
     /// # Locking (see locking.md)
@@ -589,9 +799,24 @@ impl Server {
     /// * `MemberList::entries` (write)
     /// * `RumorHeat::inner` (write)
     pub fn insert_member_mlw_rhw(&self, member: Member, health: Health) {
-        let rk: RumorKey = RumorKey::from(&member);
-        let member_id = member.id.clone();
-        if self.member_list.insert_mlw(member, health) {
+        self.insert_membership_mlw_rhw(Membership { member,
+                                                    health,
+                                                    unknown_fields: Vec::new() });
+    }
+
+    /// Like `insert_member_mlw_rhw`, but keeps the membership's unknown fields, such as those of
+    /// a membership read back from the dat file.
+    ///
+    /// # Locking (see locking.md)
+    /// * `MemberList::entries` (write)
+    /// * `RumorHeat::inner` (write)
+    pub fn insert_membership_mlw_rhw(&self, membership: Membership) {
+        let rk: RumorKey = RumorKey::from(&membership.member);
+        let member_id = membership.member.id.clone();
+        let health = membership.health;
+        let wal_copy = self.wal_copy(&membership);
+        if self.member_list.insert_membership_mlw(membership) {
+            self.append_to_wal(wal_copy);
             // Purge "heat" information for a member that's
             // gone. Purging doesn't remove Member rumor information,
             // though, since that's how we let others know this member
@@ -653,22 +878,23 @@ impl Server {
     /// * `MemberList::entries` (write)
     /// * `Server::member` (write)
     /// * `RumorHeat::inner` (write)
-    fn insert_member_from_rumor_mlw_smw_rhw(&self, member: Member, mut health: Health) {
-        let rk: RumorKey = RumorKey::from(&member);
+    fn insert_member_from_rumor_mlw_smw_rhw(&self, mut membership: Membership) {
+        let rk: RumorKey = RumorKey::from(&membership.member);
 
-        if member.id == self.member_id()
-           && health != Health::Alive
-           && member.incarnation >= self.myself.lock_smr().incarnation()
+        if membership.member.id == self.member_id()
+           && membership.health != Health::Alive
+           && membership.member.incarnation >= self.myself.lock_smr().incarnation()
         {
             self.myself
                 .lock_smw()
-                .refute_incarnation(member.incarnation);
-            health = Health::Alive;
+                .refute_incarnation(membership.member.incarnation);
+            membership.health = Health::Alive;
         }
 
-        let member_id = member.id.clone();
+        let member_id = membership.member.id.clone();
+        let health = membership.health;
 
-        if self.member_list.insert_mlw(member, health) {
+        if self.member_list.insert_membership_mlw(membership) {
             if member_id != self.member_id() && health == Health::Departed {
                 self.rumor_heat.lock_rhw().purge(&member_id);
             }
@@ -693,18 +919,29 @@ impl Server {
     /// * `MemberList::entries` (write)
     /// * `RumorHeat::inner` (write)
     pub fn insert_service_rsw_mlw_rhw(&self, service: Service) {
-        Self::insert_service_impl(service,
-                                  &self.service_store,
-                                  &self.member_list,
-                                  &self.rumor_heat,
-                                  |k| self.check_quorum_mlr(k))
+        if let Err(err) = service.validate() {
+            warn!("Rejecting {}: {}", service, err);
+            return;
+        }
+
+        let wal_copy = self.wal_copy(&service);
+        if Self::insert_service_impl(service,
+                                     &self.service_store,
+                                     &self.member_list,
+                                     &self.rumor_heat,
+                                     |k| self.check_quorum_mlr(k))
+        {
+            self.append_to_wal(wal_copy);
+        }
     }
 
+    /// Returns whether the service was inserted.
     fn insert_service_impl(service: Service,
                            service_store: &RumorStore<Service>,
                            member_list: &MemberList,
                            rumor_heat: &RumorHeat,
-                           check_quorum: impl Fn(&str) -> bool) {
+                           check_quorum: impl Fn(&str) -> bool)
+                           -> bool {
         let rk = RumorKey::from(&service);
         let RumorKey { key: service_group,
                        id: member_id,
@@ -740,6 +977,9 @@ impl Server {
             }
 
             rumor_heat.lock_rhw().start_hot_rumor(rk);
+            true
+        } else {
+            false
         }
     }
 
@@ -750,7 +990,9 @@ impl Server {
     /// * `RumorHeat::inner` (write)
     pub fn insert_service_config_rsw_rhw(&self, service_config: ServiceConfig) {
         let rk = RumorKey::from(&service_config);
+        let wal_copy = self.wal_copy(&service_config);
         if self.service_config_store.insert_rsw(service_config) {
+            self.append_to_wal(wal_copy);
             self.rumor_heat.lock_rhw().start_hot_rumor(rk);
         }
     }
@@ -761,12 +1003,131 @@ impl Server {
     /// * `RumorStore::list` (write)
     /// * `RumorHeat::inner` (write)
     pub fn insert_service_file_rsw_rhw(&self, service_file: ServiceFile) {
+        if let Err(err) = service_file.validate_size(self.service_file_limit) {
+            warn!("Rejecting {}: {}", service_file, err);
+            return;
+        }
+
         let rk = RumorKey::from(&service_file);
+        let wal_copy = self.wal_copy(&service_file);
+        // Only what identifies this version is needed once it is stored, not its body
+        let service_group = service_file.service_group.clone();
+        let filename = service_file.filename.clone();
+        let incarnation = service_file.incarnation;
+        let chunk_set = match (service_file.chunk_set(), &service_file.chunk) {
+            (Some(chunk_set), Some(chunk)) => Some((chunk_set, chunk.total)),
+            _ => None,
+        };
         if self.service_file_store.insert_rsw(service_file) {
+            if let Some((ref chunk_set, _)) = chunk_set {
+                self.service_file_chunks
+                    .lock()
+                    .expect("Service file chunk sets lock is poisoned")
+                    .entry(chunk_set.clone())
+                    .or_insert_with(SteadyTime::now);
+            }
+            self.append_to_wal(wal_copy);
             self.rumor_heat.lock_rhw().start_hot_rumor(rk);
+            self.purge_superseded_service_files_rsw_rhw(&service_group,
+                                                        &filename,
+                                                        incarnation,
+                                                        chunk_set);
         }
     }
 
+    /// Once every chunk of a version of `filename` has arrived, or straight away if it isn't
+    /// chunked, remove the rumors of older incarnations of the file. Chunks are stored under
+    /// their own ids, so newer versions don't replace them the way they replace a whole file.
+    /// `chunk_set` is the version's set and how many chunks are in it, if it is chunked.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `RumorHeat::inner` (write)
+    fn purge_superseded_service_files_rsw_rhw(&self,
+                                              service_group: &str,
+                                              filename: &str,
+                                              incarnation: u64,
+                                              chunk_set: Option<(ChunkSet, u32)>) {
+        let superseded = {
+            let store = self.service_file_store.lock_rsr();
+            let rumors = store.service_group(service_group);
+            if let Some((ref chunk_set, total)) = chunk_set {
+                let arrived = rumors.rumors()
+                                    .filter(|rumor| rumor.chunk_set().as_ref() == Some(chunk_set))
+                                    .count();
+                if arrived < total as usize {
+                    return;
+                }
+            }
+            rumors.rumors()
+                  .filter(|rumor| rumor.filename == filename && rumor.incarnation < incarnation)
+                  .cloned()
+                  .collect::<Vec<_>>()
+        };
+
+        let mut chunk_sets = self.service_file_chunks
+                                 .lock()
+                                 .expect("Service file chunk sets lock is poisoned");
+        if let Some((chunk_set, _)) = chunk_set {
+            chunk_sets.remove(&chunk_set);
+        }
+        for rumor in superseded {
+            if let Some(chunk_set) = rumor.chunk_set() {
+                chunk_sets.remove(&chunk_set);
+            }
+            self.service_file_store.remove_rsw(rumor.key(), rumor.id());
+            self.rumor_heat.lock_rhw().stop_hot_rumor(&rumor);
+        }
+    }
+
+    /// Remove the chunks of any service file whose set is still incomplete `timeout` after its
+    /// first chunk arrived, as of `now`. Returns the sets that were purged.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `RumorHeat::inner` (write)
+    pub(crate) fn purge_orphaned_service_file_chunks_rsw_rhw(&self,
+                                                             timeout: TimeDuration,
+                                                             now: SteadyTime)
+                                                             -> Vec<ChunkSet> {
+        let mut chunk_sets = self.service_file_chunks
+                                 .lock()
+                                 .expect("Service file chunk sets lock is poisoned");
+        let expired = chunk_sets.iter()
+                                .filter(|(_, &first_seen)| first_seen + timeout <= now)
+                                .map(|(chunk_set, _)| chunk_set.clone())
+                                .collect::<Vec<_>>();
+
+        let mut purged = Vec::new();
+        for chunk_set in expired {
+            chunk_sets.remove(&chunk_set);
+            let chunks = self.service_file_store
+                             .lock_rsr()
+                             .service_group(&chunk_set.service_group)
+                             .rumors()
+                             .filter(|rumor| rumor.chunk_set().as_ref() == Some(&chunk_set))
+                             .cloned()
+                             .collect::<Vec<_>>();
+            let complete = chunks.first()
+                                 .and_then(|rumor| rumor.chunk.as_ref())
+                                 .map_or(true, |chunk| chunk.total as usize == chunks.len());
+            if complete {
+                continue;
+            }
+
+            warn!("Purging {} of the chunks of service file {} for {}; the rest never arrived",
+                  chunks.len(),
+                  chunk_set.filename,
+                  chunk_set.service_group);
+            for chunk in chunks {
+                self.service_file_store.remove_rsw(chunk.key(), chunk.id());
+                self.rumor_heat.lock_rhw().stop_hot_rumor(&chunk);
+            }
+            purged.push(chunk_set);
+        }
+        purged
+    }
+
     /// Insert a departure rumor into the departure store.
     ///
     /// # Locking (see locking.md)
@@ -786,11 +1147,67 @@ impl Server {
             .lock_rhw()
             .start_hot_rumor(RumorKey::new(RumorType::Member, &departure.member_id, ""));
 
+        let wal_copy = self.wal_copy(&departure);
         if self.departure_store.insert_rsw(departure) {
+            self.append_to_wal(wal_copy);
             self.rumor_heat.lock_rhw().start_hot_rumor(rk);
         }
     }
 
+    /// Insert a departure rumor gossiped to us by `from_id`. If we already knew of the departure,
+    /// `from_id` is counted as having confirmed it.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `MemberList::entries` (write)
+    /// * `RumorHeat::inner` (write)
+    pub fn insert_departure_from_peer_rsw_mlw_rhw(&self, departure: Departure, from_id: &str) {
+        if self.departure_store
+               .contains_rsr(&departure.member_id, Departure::const_key())
+        {
+            self.departure_witnesses
+                .lock()
+                .expect("Departure witnesses lock is poisoned")
+                .entry(departure.member_id.clone())
+                .or_insert_with(DepartureWitnesses::new)
+                .confirmed_by
+                .insert(from_id.to_string());
+        }
+        self.insert_departure_rsw_mlw_rhw(departure);
+    }
+
+    /// Count `to_id` as a peer we have gossiped `member_id`'s departure to.
+    pub(crate) fn record_departure_gossiped(&self, member_id: &str, to_id: &str) {
+        self.departure_witnesses
+            .lock()
+            .expect("Departure witnesses lock is poisoned")
+            .entry(member_id.to_string())
+            .or_insert_with(DepartureWitnesses::new)
+            .gossiped_to
+            .insert(to_id.to_string());
+    }
+
+    /// How many peers we have gossiped `member_id`'s departure to, and how many have gossiped it
+    /// back to us. Departures are tracked until `purge_departure_witnesses` expires them, and
+    /// are listed in the `[departures]` section of the diagnostics.
+    pub fn departure_propagation_status(&self, member_id: &str) -> DeparturePropagation {
+        self.departure_witnesses
+            .lock()
+            .expect("Departure witnesses lock is poisoned")
+            .get(member_id)
+            .map(DepartureWitnesses::propagation)
+            .unwrap_or_default()
+    }
+
+    /// Stop tracking the departures whose first peer was counted longer than `timeout` before
+    /// `now`. Their status goes back to the default.
+    pub(crate) fn purge_departure_witnesses(&self, timeout: TimeDuration, now: SteadyTime) {
+        self.departure_witnesses
+            .lock()
+            .expect("Departure witnesses lock is poisoned")
+            .retain(|_, witnesses| witnesses.first_seen + timeout > now);
+    }
+
     /// Get all the Member ID's who are present in a given service group, and eligible to vote
     /// (alive)
     ///
@@ -870,11 +1287,12 @@ impl Server {
     pub fn start_election_rsw_mlr_rhw_msr(&self, service_group: &str, term: u64) {
         let suitability = self.suitability_lookup.suitability_for_msr(&service_group);
         let has_quorum = self.check_quorum_mlr(service_group);
-        let e = Election::new(self.member_id(),
-                              service_group,
-                              term,
-                              suitability,
-                              has_quorum);
+        let mut e = Election::new(self.member_id(),
+                                  service_group,
+                                  term,
+                                  suitability,
+                                  has_quorum);
+        e.announce_timeout = self.announce_timeout;
         if !has_quorum {
             warn!("start_election check_quorum failed: {:?}", e);
         }
@@ -894,11 +1312,12 @@ impl Server {
                                              suitability: u64,
                                              term: u64) {
         let has_quorum = self.check_quorum_mlr(service_group);
-        let e = ElectionUpdate::new(self.member_id(),
-                                    service_group,
-                                    term,
-                                    suitability,
-                                    has_quorum);
+        let mut e = ElectionUpdate::new(self.member_id(),
+                                        service_group,
+                                        term,
+                                        suitability,
+                                        has_quorum);
+        e.announce_timeout = self.announce_timeout;
         if !has_quorum {
             warn!("start_election check_quorum failed: {:?}", e);
         }
@@ -924,26 +1343,28 @@ impl Server {
                                         |k| self.check_quorum_mlr(k),
                                         &self.member_list,
                                         feature_flags,
-                                        &self.data_path)
+                                        &self.data_path,
+                                        &self.announce_deadlines,
+                                        Instant::now())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn elections_to_restart_impl<T>(elections: &RumorStore<T>,
                                     service_store: &RumorStore<Service>,
                                     myself_member_id: &str,
                                     check_quorum: impl Fn(&str) -> bool,
                                     member_list: &MemberList,
                                     feature_flags: FeatureFlag,
-                                    data_path: &Option<PathBuf>)
+                                    data_path: &Option<PathBuf>,
+                                    announce_deadlines: &AnnounceDeadlines,
+                                    now: Instant)
                                     -> Vec<(String, u64)>
         where T: ConstIdRumor + ElectionRumor + Debug
     {
         let mut elections_to_restart = vec![];
 
         for (service_group, rumors) in elections.lock_rsr().iter() {
-            if service_store.lock_rsr()
-                            .service_group(&service_group)
-                            .contains_id(myself_member_id)
-            {
+            if service_store.contains_rsr(myself_member_id, &service_group) {
                 // This is safe; there is only one id for a ConstIdRumor
                 let election =
                     rumors.get(T::const_id())
@@ -983,6 +1404,16 @@ impl Server {
                             elections_to_restart.push((String::from(&service_group[..]),
                                                        election.term()));
                         }
+                    } else if Self::winner_announce_overdue(election,
+                                                            myself_member_id,
+                                                            announce_deadlines,
+                                                            now)
+                    {
+                        warn!("Restarting election with a new term as the candidate never \
+                               announced that it won {}: {:?}",
+                              myself_member_id, election);
+                        elections_to_restart.push((String::from(&service_group[..]),
+                                                   election.term()));
                     }
                 }
             }
@@ -991,10 +1422,48 @@ impl Server {
         elections_to_restart
     }
 
+    /// Has the candidate of this running election had longer than the election's winner
+    /// announce timeout to finish it? The countdown starts the first time we see a candidate
+    /// other than ourselves for a term, and is reset whenever the candidate or term changes.
+    fn winner_announce_overdue<T>(election: &T,
+                                  myself_member_id: &str,
+                                  announce_deadlines: &AnnounceDeadlines,
+                                  now: Instant)
+                                  -> bool
+        where T: ConstIdRumor + ElectionRumor
+    {
+        let rumor_key = RumorKey::from(election);
+        let mut deadlines = announce_deadlines.lock()
+                                              .expect("Announce deadlines lock poisoned");
+        let timeout = election.announce_timeout();
+        if !election.is_running()
+           || election.member_id() == myself_member_id
+           || timeout == Duration::from_secs(0)
+        {
+            deadlines.remove(&rumor_key);
+            return false;
+        }
+
+        let is_counting_down =
+            deadlines.get(&rumor_key)
+                     .map_or(false, |d| {
+                         d.term == election.term() && d.member_id == election.member_id()
+                     });
+        if !is_counting_down {
+            deadlines.insert(rumor_key.clone(),
+                             AnnounceDeadline { term:      election.term(),
+                                                member_id: election.member_id().to_string(),
+                                                deadline:  now + timeout, });
+        }
+        now >= deadlines[&rumor_key].deadline
+    }
+
     /// Check to see if this server needs to restart a given election. This happens when:
     ///
     /// a) We are the leader, and we have lost quorum with the rest of the group.
     /// b) We are not the leader, and we have detected that the leader is confirmed dead.
+    /// c) We are not the candidate, and the candidate has not announced that it won before the
+    ///    election's winner announce timeout.
     ///
     /// # Locking (see locking.md)
     /// * `RumorStore::list` (write)
@@ -1029,6 +1498,42 @@ impl Server {
         }
     }
 
+    /// Insert an election read back from the dat file. An election this member had won says
+    /// nothing about whether the rest of the ring still agrees; another member may have won a
+    /// newer term while we were down. Rather than taking up leadership straight off the disk,
+    /// the election is reopened and marked as restored (see `Election::reopen_restored`), so
+    /// that the vote has to be confirmed again.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `MemberList::entries` (read)
+    /// * `RumorHeat::inner` (write)
+    /// * `ManagerServices::inner` (read)
+    pub fn restore_election_rsw_mlr_rhw_msr(&self, mut election: Election) {
+        if election.member_id == self.member_id() && election.is_finished() {
+            info!("Reconfirming restored election for {} term {}, which this member won",
+                  election.service_group, election.term);
+            election.reopen_restored();
+        }
+        self.insert_election_rsw_mlr_rhw_msr(election);
+    }
+
+    /// Insert an election update read back from the dat file. See
+    /// `restore_election_rsw_mlr_rhw_msr`.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (write)
+    /// * `MemberList::entries` (read)
+    /// * `RumorHeat::inner` (write)
+    pub fn restore_update_election_rsw_mlr_rhw(&self, mut election: ElectionUpdate) {
+        if election.member_id == self.member_id() && election.is_finished() {
+            info!("Reconfirming restored election update for {} term {}, which this member won",
+                  election.service_group, election.term);
+            election.reopen_restored();
+        }
+        self.insert_update_election_rsw_mlr_rhw(election);
+    }
+
     /// Insert an election into the election store. Handles creating a new election rumor for this
     /// member on receipt of an election rumor for a service this server cares about. Also handles
     /// stopping the election if we are the winner and we have enough votes.
@@ -1044,9 +1549,7 @@ impl Server {
 
         // If this is an election for a service group we care about
         if self.service_store
-               .lock_rsr()
-               .service_group(&election.service_group)
-               .contains_id(self.member_id())
+               .contains_rsr(self.member_id(), &election.service_group)
         {
             trace!("{} is a member of {}",
                    self.member_id(),
@@ -1125,7 +1628,9 @@ impl Server {
             }
         }
 
+        let wal_copy = self.wal_copy(&election);
         if self.election_store.insert_rsw(election) {
+            self.append_to_wal(wal_copy);
             self.rumor_heat.lock_rhw().start_hot_rumor(rk);
         }
     }
@@ -1140,9 +1645,7 @@ impl Server {
 
         // If this is an election for a service group we care about
         if self.service_store
-               .lock_rsr()
-               .service_group(&election.service_group)
-               .contains_id(self.member_id())
+               .contains_rsr(self.member_id(), &election.service_group)
         {
             trace!("{} is a member of {}",
                    self.member_id(),
@@ -1201,7 +1704,9 @@ impl Server {
             }
         }
 
+        let wal_copy = self.wal_copy(&election);
         if self.update_store.insert_rsw(election) {
+            self.append_to_wal(wal_copy);
             self.rumor_heat.lock_rhw().start_hot_rumor(rk);
         }
     }
@@ -1214,30 +1719,226 @@ impl Server {
         message::unwrap_wire(payload, (*self.ring_key).as_ref())
     }
 
+    /// Persist the rumors inserted since the last call. When the write-ahead log is acting as a
+    /// journal, they are already on disk, so the dat file is only rewritten once the log needs
+    /// compacting, or a rumor couldn't be appended to it.
+    ///
     /// # Locking (see locking.md)
     /// * `RumorStore::list` (read)
     /// * `MemberList::entries` (read)
     pub fn persist_data_rsr_mlr(&self) {
-        if let Some(ref dat_file_lock) = self.dat_file {
-            let dat_file = dat_file_lock.lock().expect("DatFile lock poisoned");
-            if let Some(err) = dat_file.write_rsr_mlr(&self.member_list,
-                                                      &self.service_store,
-                                                      &self.service_config_store,
-                                                      &self.service_file_store,
-                                                      &self.election_store,
-                                                      &self.update_store,
-                                                      &self.departure_store)
-                                       .err()
-            {
-                error!("Error persisting rumors to disk, {}", err);
+        if let Some(ref wal) = self.dat_file_wal {
+            match wal.needs_compaction() {
+                Ok(true) => (),
+                Ok(false) => {
+                    trace!("Leaving changes in {} until it needs compacting",
+                           wal.path().display());
+                    return;
+                }
+                Err(err) => error!("Error reading write-ahead log length, {}", err),
+            }
+        }
+        self.compact_rsr_mlr();
+    }
+
+    /// Rewrite the dat file from scratch with every current rumor, and drop the write-ahead log
+    /// entries it now contains. The rumors are copied into a snapshot first, so the locks are
+    /// only held while they are cloned, not while they are encoded and written.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    pub fn compact_rsr_mlr(&self) {
+        if let Some(ref persistence) = self.persistence {
+            // Every entry already in the write-ahead log was inserted before we start writing,
+            // so it will be in the dat file and can be checkpointed once the write succeeds.
+            let wal_len = match self.dat_file_wal.as_ref().map(|wal| wal.size()) {
+                Some(Ok(len)) => Some(len),
+                Some(Err(err)) => {
+                    error!("Error reading write-ahead log length, {}", err);
+                    None
+                }
+                None => None,
+            };
+            // A rumor that was missed has been inserted by now, so the snapshot will hold it
+            let missed = self.dat_file_wal
+                             .as_ref()
+                             .map_or(false, |wal| wal.take_missed());
+            let snapshot = RumorSnapshot::take_rsr_mlr(self);
+            let written = snapshot.and_then(|snapshot| persistence.write_snapshot(&snapshot));
+            if let Some(err) = written.err() {
+                error!("Error persisting rumors to {}, {}", persistence, err);
+                if let (Some(wal), true) = (&self.dat_file_wal, missed) {
+                    wal.mark_missed();
+                }
             } else {
-                info!("Rumors persisted to disk: {}", dat_file.path().display());
+                info!("Rumors persisted to {}", persistence);
+                if let (Some(wal), Some(len)) = (&self.dat_file_wal, wal_len) {
+                    if let Err(err) = wal.checkpoint(len) {
+                        error!("Error checkpointing write-ahead log, {}", err);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Persist every rumor one last time as the Supervisor shuts down, unless
+    /// `PersistConfig::on_shutdown` is unset, in which case anything changed since the persist
+    /// thread last wrote is lost.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    pub fn persist_on_shutdown_rsr_mlr(&self) {
+        if self.persist_config.on_shutdown {
+            self.compact_rsr_mlr();
+        } else {
+            debug!("Not persisting rumors on shutdown");
+        }
+    }
+
+    /// A copy of a rumor to append to the write-ahead log once it has been inserted, if we are
+    /// keeping one.
+    fn wal_copy<T: Clone>(&self, rumor: &T) -> Option<T> {
+        self.dat_file_wal.as_ref().map(|_| rumor.clone())
+    }
+
+    fn append_to_wal<P, T>(&self, rumor: Option<T>)
+        where P: ProstMessage + Default,
+              T: protocol::Message<P>
+    {
+        if let (Some(wal), Some(rumor)) = (&self.dat_file_wal, rumor) {
+            if let Err(err) = rumor.write_to_bytes()
+                                   .and_then(|bytes| wal.append(T::MESSAGE_ID, &bytes))
+            {
+                error!("Error appending to write-ahead log {}, {}",
+                       wal.path().display(),
+                       err);
+                wal.mark_missed();
             }
         }
     }
 
     #[allow(dead_code)]
     pub fn is_departed(&self) -> bool { self.departed.load(Ordering::Relaxed) }
+
+    /// Write a human-readable snapshot of this server's state, intended for operators inspecting
+    /// a running supervisor.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    /// * `Server::member` (read)
+    pub fn dump_diagnostics_rsr_mlr_smr(&self, writer: &mut dyn io::Write) -> io::Result<()> {
+        let (member, incarnation) = {
+            let myself = self.myself.lock_smr();
+            (myself.to_member(), myself.incarnation())
+        };
+        writeln!(writer, "[member]")?;
+        writeln!(writer, "id: {}", member.id)?;
+        writeln!(writer, "name: {}", self.name())?;
+        writeln!(writer, "incarnation: {}", incarnation)?;
+        writeln!(writer, "swim address: {}", self.swim_addr)?;
+        writeln!(writer, "gossip address: {}", self.gossip_addr)?;
+        writeln!(writer, "departed: {}", self.is_departed())?;
+
+        let health_counts = self.member_list.health_counts_mlr();
+        writeln!(writer, "\n[ring]")?;
+        writeln!(writer, "members: {}", health_counts.values().sum::<usize>())?;
+        for health in [Health::Alive,
+                       Health::Suspect,
+                       Health::Confirmed,
+                       Health::Departed].iter()
+        {
+            writeln!(writer,
+                     "{}: {}",
+                     health,
+                     health_counts.get(health).unwrap_or(&0))?;
+        }
+        for &health in &[Health::Suspect, Health::Confirmed] {
+            let mut ids: Vec<_> = self.peers_by_health_mlr(health)
+                                      .into_iter()
+                                      .map(|member| member.id)
+                                      .collect();
+            if !ids.is_empty() {
+                ids.sort();
+                writeln!(writer, "{} ids: {}", health, ids.join(", "))?;
+            }
+        }
+
+        writeln!(writer, "\n[rumors]")?;
+        writeln!(writer,
+                 "service: {}",
+                 self.service_store.lock_rsr().rumors().count())?;
+        writeln!(writer,
+                 "service config: {}",
+                 self.service_config_store.lock_rsr().rumors().count())?;
+        writeln!(writer,
+                 "service file: {}",
+                 self.service_file_store.lock_rsr().rumors().count())?;
+        writeln!(writer,
+                 "election: {}",
+                 self.election_store.lock_rsr().rumors().count())?;
+        writeln!(writer,
+                 "election update: {}",
+                 self.update_store.lock_rsr().rumors().count())?;
+        writeln!(writer,
+                 "departure: {}",
+                 self.departure_store.lock_rsr().rumors().count())?;
+
+        writeln!(writer, "\n[rumor locks]")?;
+        write_lock_metrics(writer, "service", self.service_store.lock_metrics())?;
+        write_lock_metrics(writer,
+                           "service config",
+                           self.service_config_store.lock_metrics())?;
+        write_lock_metrics(writer,
+                           "service file",
+                           self.service_file_store.lock_metrics())?;
+        write_lock_metrics(writer, "election", self.election_store.lock_metrics())?;
+        write_lock_metrics(writer, "election update", self.update_store.lock_metrics())?;
+        write_lock_metrics(writer, "departure", self.departure_store.lock_metrics())?;
+
+        writeln!(writer, "\n[gossip]")?;
+        writeln!(writer, "paused: {}", self.paused())?;
+        writeln!(writer, "swim rounds: {}", self.swim_rounds())?;
+        writeln!(writer, "gossip rounds: {}", self.gossip_rounds())?;
+
+        writeln!(writer, "\n[departures]")?;
+        let departures = self.departure_witnesses
+                             .lock()
+                             .expect("Departure witnesses lock is poisoned")
+                             .iter()
+                             .map(|(id, witnesses)| (id.clone(), witnesses.propagation()))
+                             .collect::<BTreeMap<_, _>>();
+        for (id, propagation) in departures {
+            writeln!(writer,
+                     "{}: gossiped to {}, confirmed by {}",
+                     id, propagation.gossiped_to, propagation.confirmed_by)?;
+        }
+
+        writeln!(writer, "\n[persistence]")?;
+        match self.persistence {
+            Some(ref persistence) => persistence.write_diagnostics(writer)?,
+            None => writeln!(writer, "location: none")?,
+        }
+
+        writeln!(writer, "\n[uptime]")?;
+        writeln!(writer, "seconds: {}", self.start_time.elapsed().as_secs())?;
+        Ok(())
+    }
+}
+
+/// One line of `dump_diagnostics_rsr_mlr_smr`'s `[rumor locks]` section.
+fn write_lock_metrics(writer: &mut dyn io::Write,
+                      store: &str,
+                      metrics: &RumorStoreLockMetrics)
+                      -> io::Result<()> {
+    writeln!(writer,
+             "{}: read wait {}us, write wait {}us, longest write wait {}us",
+             store,
+             metrics.total_read_wait_us(),
+             metrics.total_write_wait_us(),
+             metrics.max_write_wait_us())
 }
 
 impl fmt::Display for Server {
@@ -1257,29 +1958,51 @@ fn spawn_persist_thread(name: String, server: Server) -> std::io::Result<()> {
 }
 
 fn persist_loop(server: &Server) -> ! {
-    habitat_core::env_config_duration!(PersistLoopPeriod,
-                                       HAB_PERSIST_LOOP_PERIOD_SECS => from_secs,
-                                       Duration::from_secs(30));
-
-    let min_loop_period: Duration = PersistLoopPeriod::configured_value().into();
+    let config = &server.persist_config;
+    // When the last write started, and the update counter as it was then
+    let mut last_persist: Option<(Instant, usize)> = None;
 
     loop {
         liveliness_checker::mark_thread_alive().and_divergent();
 
-        let before_persist = Instant::now();
-        server.persist_data_rsr_mlr();
-        let time_to_persist = before_persist.elapsed();
-        trace!("persist_data took {:?}", time_to_persist);
-        match min_loop_period.checked_sub(time_to_persist) {
-            Some(time_to_wait) => thread::sleep(time_to_wait),
-            None => {
+        let update_counter = server.update_counter();
+        let due = last_persist.map_or(true, |(persisted_at, persisted_counter)| {
+                                  persist_due(config,
+                                              persisted_at.elapsed(),
+                                              update_counter.wrapping_sub(persisted_counter))
+                              });
+        if due {
+            let before_persist = Instant::now();
+            server.persist_data_rsr_mlr();
+            let time_to_persist = before_persist.elapsed();
+            trace!("persist_data took {:?}", time_to_persist);
+            if time_to_persist > config.interval {
                 warn!("Persisting data took longer than expected: {:?}",
                       time_to_persist)
             }
+            last_persist = Some((before_persist, update_counter));
+        }
+
+        let since_persist = last_persist.map(|(persisted_at, _)| persisted_at.elapsed())
+                                        .unwrap_or_default();
+        let time_to_wait = config.interval
+                                 .checked_sub(since_persist)
+                                 .unwrap_or_default();
+        if config.after_changes.is_some() {
+            thread::sleep(time_to_wait.min(PERSIST_CHANGES_POLL_PERIOD));
+        } else {
+            thread::sleep(time_to_wait);
         }
     }
 }
 
+/// Whether the persist thread should write, `since_persist` after it last did with `changes`
+/// members and rumors changed since.
+fn persist_due(config: &PersistConfig, since_persist: Duration, changes: usize) -> bool {
+    let enough_changes = |after_changes| changes > 0 && changes >= after_changes;
+    since_persist >= config.interval || config.after_changes.map_or(false, enough_changes)
+}
+
 /// This is a proxy struct to represent what information we're writing to the dat file, and
 /// therefore what information gets sent out via the HTTP API. Right now, we're just wrapping the
 /// actual Server struct, but this will give us something we can refactor against without
@@ -1399,7 +2122,7 @@ mod tests {
     fn mock_service(member: &Member) -> Service {
         Service { member_id:     member.id.clone(),
                   service_group: ServiceGroup::from_str("group.default").unwrap(),
-                  incarnation:   Default::default(),
+                  incarnation:   1,
                   initialized:   Default::default(),
                   pkg:           Default::default(),
                   cfg:           Default::default(),
@@ -1434,7 +2157,9 @@ mod tests {
                                                            check_quorum_returns(true),
                                                            &member_list,
                                                            FeatureFlag::empty(),
-                                                           &None);
+                                                           &None,
+                                                           &Mutex::new(HashMap::new()),
+                                                           Instant::now());
 
         assert_eq!(to_restart, vec![(service.service_group.to_string(), term)]);
     }
@@ -1469,11 +2194,90 @@ mod tests {
                                                            check_quorum_returns(true),
                                                            &member_list,
                                                            FeatureFlag::empty(),
-                                                           &None);
+                                                           &None,
+                                                           &Mutex::new(HashMap::new()),
+                                                           Instant::now());
 
         assert_eq!(to_restart, vec![(service.service_group.to_string(), term)]);
     }
 
+    #[test]
+    fn elections_are_restarted_when_candidate_never_announces_it_won() {
+        env_logger::try_init().ok();
+        let elections = RumorStore::<Election>::default();
+        let service_store = RumorStore::<Service>::default();
+        let myself = Member::default();
+        let crashed_candidate = Member::default();
+        let member_list = MemberList::new();
+        let service = mock_service(&myself);
+        let timeout = Duration::from_secs(60);
+
+        // The candidate is still alive as far as the ring knows, but crashed before it could
+        // finish the election.
+        let mut election = Election::new(crashed_candidate.id.clone(),
+                                         &service.service_group,
+                                         Term::default(),
+                                         1,
+                                         true /* has_quorum */);
+        election.announce_timeout = timeout;
+        elections.insert_rsw(election);
+        service_store.insert_rsw(service.clone());
+        member_list.insert_mlw(crashed_candidate, Health::Alive);
+
+        let deadlines = Mutex::new(HashMap::new());
+        let declared_at = Instant::now();
+        let to_restart_at = |now| {
+            Server::elections_to_restart_impl(&elections,
+                                              &service_store,
+                                              &myself.id,
+                                              check_quorum_returns(true),
+                                              &member_list,
+                                              FeatureFlag::empty(),
+                                              &None,
+                                              &deadlines,
+                                              now)
+        };
+
+        assert!(to_restart_at(declared_at).is_empty());
+        assert!(to_restart_at(declared_at + timeout - Duration::from_secs(1)).is_empty());
+        assert_eq!(to_restart_at(declared_at + timeout),
+                   vec![(service.service_group.to_string(), Term::default())]);
+    }
+
+    #[test]
+    fn elections_are_not_restarted_while_this_member_is_the_candidate() {
+        let elections = RumorStore::<Election>::default();
+        let service_store = RumorStore::<Service>::default();
+        let myself = Member::default();
+        let member_list = MemberList::new();
+        let service = mock_service(&myself);
+        let timeout = Duration::from_secs(60);
+
+        let mut election = Election::new(myself.id.clone(),
+                                         &service.service_group,
+                                         Term::default(),
+                                         1,
+                                         true /* has_quorum */);
+        election.announce_timeout = timeout;
+        elections.insert_rsw(election);
+        service_store.insert_rsw(service.clone());
+
+        let deadlines = Mutex::new(HashMap::new());
+        let started_at = Instant::now();
+        for now in &[started_at, started_at + timeout * 2] {
+            let to_restart = Server::elections_to_restart_impl(&elections,
+                                                               &service_store,
+                                                               &myself.id,
+                                                               check_quorum_returns(true),
+                                                               &member_list,
+                                                               FeatureFlag::empty(),
+                                                               &None,
+                                                               &deadlines,
+                                                               *now);
+            assert!(to_restart.is_empty());
+        }
+    }
+
     #[test]
     fn insert_service_adds_service_to_service_store() {
         let service = mock_service(&Member::default());
@@ -1647,16 +2451,27 @@ mod tests {
     mod server {
         use super::*;
         use crate::{member::Member,
-                    server::{timing::Timing,
+                    protocol::Message,
+                    rumor::persistence::MemoryPersistence,
+                    server::{timing::{MemberSelector,
+                                      TimeoutOverride,
+                                      Timing},
                              Server,
                              Suitability}};
-        use std::{fs::File,
+        use habitat_common::locked_env_var;
+        use std::{fs::{File,
+                       OpenOptions},
                   io::prelude::*,
                   net::{IpAddr,
                         Ipv4Addr,
                         SocketAddr},
                   sync::Mutex};
         use tempfile::TempDir;
+        use time::{Duration,
+                   SteadyTime};
+
+        locked_env_var!(HAB_PERSIST_LOOP_PERIOD_SECS, lock_persist_loop_period);
+        locked_env_var!(HAB_NO_PERSIST_ON_SHUTDOWN, lock_no_persist_on_shutdown);
 
         lazy_static! {
             static ref SWIM_PORT: Mutex<u16> = Mutex::new(6666);
@@ -1669,7 +2484,9 @@ mod tests {
             fn suitability_for_msr(&self, _service_group: &str) -> u64 { 0 }
         }
 
-        fn start_server() -> Server {
+        fn start_server() -> Server { new_server(Member::default(), None) }
+
+        fn new_server(mut member: Member, data_path: Option<&Path>) -> Server {
             let swim_port;
             {
                 let mut swim_port_guard = SWIM_PORT.lock().expect("SWIM_PORT poisoned");
@@ -1685,7 +2502,6 @@ mod tests {
             }
             let gossip_listen =
                 SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), gossip_port);
-            let mut member = Member::default();
             member.swim_port = swim_port;
             member.gossip_port = gossip_port;
             Server::new(swim_listen,
@@ -1693,40 +2509,43 @@ mod tests {
                         member,
                         None,
                         None,
-                        None,
+                        data_path,
                         Arc::new(ZeroSuitability)).unwrap()
         }
 
-        fn start_with_corrupt_rumor_file(tmpdir: &TempDir) -> Server {
-            let swim_port;
-            {
-                let mut swim_port_guard = SWIM_PORT.lock().expect("SWIM_PORT poisoned");
-                swim_port = *swim_port_guard;
-                *swim_port_guard += 1;
-            }
-            let swim_listen = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), swim_port);
-            let gossip_port;
-            {
-                let mut gossip_port_guard = GOSSIP_PORT.lock().expect("GOSSIP_PORT poisoned");
-                gossip_port = *gossip_port_guard;
-                *gossip_port_guard += 1;
-            }
-            let gossip_listen =
-                SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), gossip_port);
+        fn member_in_zone(zone: &str) -> Member {
             let mut member = Member::default();
-            member.swim_port = swim_port;
-            member.gossip_port = gossip_port;
-            let rumor_name = format!("{}{}", member.id.to_string(), ".rst");
+            member.labels
+                  .insert(ZONE_LABEL.to_string(), zone.to_string());
+            member
+        }
+
+        /// Start a server in the "east" zone, with 4 other members in "east" and 5 in "west".
+        fn start_server_in_two_zones(zoned: bool) -> Server {
+            let server = if zoned {
+                new_server(member_in_zone("east"), None)
+            } else {
+                start_server()
+            };
+            for zone in ["east"; 4].iter().chain(["west"; 5].iter()) {
+                server.insert_member_mlw_rhw(member_in_zone(zone), Health::Alive);
+            }
+            server
+        }
+
+        fn cross_zone_count(peers: &[Member]) -> usize {
+            peers.iter()
+                 .filter(|peer| peer.labels[ZONE_LABEL] != "east")
+                 .count()
+        }
+
+        fn start_with_corrupt_rumor_file(tmpdir: &TempDir) -> Server {
+            let server = new_server(Member::default(), Some(tmpdir.path()));
+            let rumor_name = format!("{}{}", server.member_id(), ".rst");
             let file_path = tmpdir.path().to_owned().join(rumor_name);
             let mut rumor_file = File::create(file_path).unwrap();
             writeln!(rumor_file, "This is not a valid rumor file!").unwrap();
-            Server::new(swim_listen,
-                        gossip_listen,
-                        member,
-                        None,
-                        None,
-                        Some(tmpdir.path()),
-                        Arc::new(ZeroSuitability)).unwrap()
+            server
         }
 
         #[test]
@@ -1747,5 +2566,676 @@ mod tests {
             server.start_rsw_mlw_smw_rhw_msr(&Timing::default())
                   .expect("Server failed to start");
         }
+
+        /// A server running a service in `group.default`, so that it takes part in that group's
+        /// elections.
+        fn server_in_group() -> Server {
+            let member = Member::default();
+            let server = new_server(member.clone(), None);
+            server.insert_service_rsw_mlw_rhw(mock_service(&member));
+            server
+        }
+
+        #[test]
+        fn invalid_service_rumors_are_rejected() {
+            let server = start_server();
+            let member = Member::default();
+            server.insert_service_rsw_mlw_rhw(Service { incarnation: 0,
+                                                        ..mock_service(&member) });
+            server.insert_service_rsw_mlw_rhw(Service { member_id: String::new(),
+                                                        ..mock_service(&member) });
+            assert_eq!(server.service_store.lock_rsr().rumors().count(), 0);
+
+            server.insert_service_rsw_mlw_rhw(mock_service(&member));
+            assert!(server.service_store
+                          .contains_rsr(&member.id, "group.default"));
+        }
+
+        fn finished_election(member_id: &str, term: u64) -> Election {
+            let mut election = Election::new(member_id, "group.default", term, 0, true);
+            election.finish();
+            election
+        }
+
+        #[test]
+        fn restored_election_won_by_this_member_is_reopened() {
+            let server = server_in_group();
+
+            server.restore_election_rsw_mlr_rhw_msr(finished_election(server.member_id(), 1));
+
+            let election = server.election_store
+                                 .get_rsr(Election::const_id(), "group.default")
+                                 .expect("election stored");
+            assert_eq!(election.member_id, server.member_id());
+            assert!(!election.is_finished());
+        }
+
+        #[test]
+        fn restored_election_won_by_this_member_needs_the_ring_to_vote_again() {
+            let server = server_in_group();
+            let mut persisted = finished_election(server.member_id(), 1);
+            persisted.insert_vote("other-member");
+
+            server.restore_election_rsw_mlr_rhw_msr(persisted);
+
+            let election = server.election_store
+                                 .get_rsr(Election::const_id(), "group.default")
+                                 .expect("election stored");
+            assert!(election.restored);
+            assert_eq!(election.votes, vec![server.member_id().to_string()]);
+        }
+
+        #[test]
+        fn restored_election_gives_way_to_a_newer_term_won_elsewhere() {
+            let server = server_in_group();
+            server.restore_election_rsw_mlr_rhw_msr(finished_election(server.member_id(), 1));
+
+            server.insert_election_rsw_mlr_rhw_msr(finished_election("other-member", 2));
+
+            let election = server.election_store
+                                 .get_rsr(Election::const_id(), "group.default")
+                                 .expect("election stored");
+            assert_eq!(election.member_id, "other-member");
+            assert_eq!(election.term, 2);
+            assert!(election.is_finished());
+        }
+
+        #[test]
+        fn restored_election_won_by_another_member_stays_finished() {
+            let server = server_in_group();
+
+            server.restore_election_rsw_mlr_rhw_msr(finished_election("other-member", 1));
+
+            let election = server.election_store
+                                 .get_rsr(Election::const_id(), "group.default")
+                                 .expect("election stored");
+            assert_eq!(election.member_id, "other-member");
+            assert!(election.is_finished());
+            assert!(!election.restored);
+        }
+
+        #[test]
+        fn restored_election_update_won_by_this_member_is_reopened() {
+            let server = server_in_group();
+            let mut update = ElectionUpdate::new(server.member_id(), "group.default", 1, 0, true);
+            update.finish();
+
+            server.restore_update_election_rsw_mlr_rhw(update);
+
+            let update = server.update_store
+                               .get_rsr(ElectionUpdate::const_id(), "group.default")
+                               .expect("election update stored");
+            assert_eq!(update.member_id, server.member_id());
+            assert!(!update.is_finished());
+            assert!(update.restored);
+        }
+
+        #[test]
+        fn peers_by_health_returns_only_members_in_that_state() {
+            let server = start_server();
+            let mut ids_by_health = HashMap::new();
+            for &health in &[Health::Alive,
+                             Health::Alive,
+                             Health::Suspect,
+                             Health::Confirmed,
+                             Health::Departed]
+            {
+                let member = Member::default();
+                ids_by_health.entry(health)
+                             .or_insert_with(HashSet::new)
+                             .insert(member.id.clone());
+                server.insert_member_mlw_rhw(member, health);
+            }
+
+            for &health in &[Health::Alive,
+                             Health::Suspect,
+                             Health::Confirmed,
+                             Health::Departed]
+            {
+                let ids: HashSet<_> = server.peers_by_health_mlr(health)
+                                            .into_iter()
+                                            .map(|member| member.id)
+                                            .collect();
+                assert_eq!(ids, ids_by_health[&health], "{} members", health);
+            }
+        }
+
+        #[test]
+        fn peers_by_health_is_empty_when_no_member_is_in_that_state() {
+            let server = start_server();
+            for &health in &[Health::Alive,
+                             Health::Suspect,
+                             Health::Confirmed,
+                             Health::Departed]
+            {
+                assert!(server.peers_by_health_mlr(health).is_empty());
+            }
+
+            server.insert_member_mlw_rhw(Member::default(), Health::Alive);
+            assert_eq!(server.peers_by_health_mlr(Health::Alive).len(), 1);
+            assert!(server.peers_by_health_mlr(Health::Suspect).is_empty());
+        }
+
+        #[test]
+        fn departure_propagation_counts_each_peer_once() {
+            let server = start_server();
+            server.insert_departure_from_peer_rsw_mlw_rhw(Departure::new("departed-member"),
+                                                          "first-teller");
+            server.record_departure_gossiped("departed-member", "peer-a");
+            server.record_departure_gossiped("departed-member", "peer-a");
+            server.record_departure_gossiped("departed-member", "peer-b");
+            server.insert_departure_from_peer_rsw_mlw_rhw(Departure::new("departed-member"),
+                                                          "peer-a");
+            server.insert_departure_from_peer_rsw_mlw_rhw(Departure::new("departed-member"),
+                                                          "peer-a");
+
+            assert_eq!(server.departure_propagation_status("departed-member"),
+                       DeparturePropagation { gossiped_to:  2,
+                                              confirmed_by: 1, });
+            assert_eq!(server.departure_propagation_status("someone-else"),
+                       DeparturePropagation::default());
+        }
+
+        #[test]
+        fn expire_stops_tracking_old_departures() {
+            let server = start_server();
+            let timing = Timing::default();
+            let recorded_at = SteadyTime::now();
+            server.record_departure_gossiped("departed-member", "peer-a");
+
+            expire::run_once(&server, &timing, recorded_at);
+            assert_eq!(server.departure_propagation_status("departed-member")
+                             .gossiped_to,
+                       1);
+
+            let past_timeout =
+                recorded_at + timing.departure_witness_timeout_duration() + Duration::seconds(1);
+            expire::run_once(&server, &timing, past_timeout);
+            assert_eq!(server.departure_propagation_status("departed-member"),
+                       DeparturePropagation::default());
+        }
+
+        #[test]
+        fn dump_diagnostics_includes_all_sections() {
+            let server = start_server();
+            let suspect = Member::default();
+            let suspect_id = suspect.id.clone();
+            server.insert_member_mlw_rhw(suspect, Health::Suspect);
+            server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+
+            let mut output = Vec::new();
+            server.dump_diagnostics_rsr_mlr_smr(&mut output)
+                  .expect("diagnostics written");
+            let output = String::from_utf8(output).expect("diagnostics are utf-8");
+
+            for section in &["[member]",
+                             "[ring]",
+                             "[rumors]",
+                             "[rumor locks]",
+                             "[gossip]",
+                             "[departures]",
+                             "[persistence]",
+                             "[uptime]"]
+            {
+                assert!(output.contains(section),
+                        "missing {} in:\n{}",
+                        section,
+                        output);
+            }
+            assert!(output.contains(&format!("id: {}", server.member_id())));
+            assert!(output.contains("suspect: 1"));
+            assert!(output.contains(&format!("suspect ids: {}", suspect_id)));
+            assert!(!output.contains("confirmed ids:"));
+            assert!(output.contains("departure: 1"));
+
+            server.record_departure_gossiped("departed-member", "peer-a");
+            let mut output = Vec::new();
+            server.dump_diagnostics_rsr_mlr_smr(&mut output)
+                  .expect("diagnostics written");
+            let output = String::from_utf8(output).expect("diagnostics are utf-8");
+            assert!(output.contains("departed-member: gossiped to 1, confirmed by 0"));
+        }
+
+        #[test]
+        fn gossip_peers_for_round_gives_each_batch_up_to_half_from_the_same_zone() {
+            let unzoned = start_server_in_two_zones(false).gossip_peers_for_round_mlr_smr();
+            assert_eq!(unzoned.len(), 9);
+            assert_eq!(cross_zone_count(&unzoned), 5);
+
+            let server = start_server_in_two_zones(true);
+            for _ in 0..20 {
+                let peers = server.gossip_peers_for_round_mlr_smr();
+                assert_eq!(peers.len(), 9);
+                let batches: Vec<_> = peers.chunks(push::FANOUT).collect();
+                assert_eq!(batches.len(), 2);
+                assert_eq!(cross_zone_count(batches[0]),
+                           push::FANOUT - push::FANOUT / 2);
+                assert_eq!(cross_zone_count(batches[1]), 2);
+            }
+        }
+
+        #[test]
+        fn gossip_peers_for_round_tops_up_from_the_same_zone_when_other_zones_are_small() {
+            let server = new_server(member_in_zone("east"), None);
+            for _ in 0..6 {
+                server.insert_member_mlw_rhw(member_in_zone("east"), Health::Alive);
+            }
+            server.insert_member_mlw_rhw(member_in_zone("west"), Health::Alive);
+
+            let peers = server.gossip_peers_for_round_mlr_smr();
+            assert_eq!(peers.len(), 7);
+            assert_eq!(cross_zone_count(&peers[..push::FANOUT]), 1);
+        }
+
+        #[test]
+        fn rumors_in_the_wal_are_replayed_on_start() {
+            let tmpdir = TempDir::new().unwrap();
+            let mut server = new_server(Member::default(), Some(tmpdir.path()));
+            let dat_path = tmpdir.path().join(format!("{}.rst", server.member_id()));
+
+            // A previous run appended these rumors, then died before persisting the dat file,
+            // partway through appending one more.
+            let wal = DatFileWal::open(&dat_path).expect("wal opened");
+            let peer = Member::default();
+            let membership = Membership { member:         peer.clone(),
+                                          health:         Health::Alive,
+                                          unknown_fields: Vec::new(), };
+            wal.append(Membership::MESSAGE_ID,
+                       &membership.write_to_bytes().unwrap())
+               .expect("member appended");
+            for service_group in &["witcher.prod", "witcher.dev", "witcher.qa"] {
+                let service = Service { service_group:
+                                            ServiceGroup::from_str(service_group).unwrap(),
+                                        ..mock_service(&peer) };
+                wal.append(Service::MESSAGE_ID, &service.write_to_bytes().unwrap())
+                   .expect("service appended");
+            }
+            let departure = Departure::new("departed-member");
+            let bytes = departure.write_to_bytes().unwrap();
+            wal.append(Departure::MESSAGE_ID, &bytes)
+               .expect("departure appended");
+            let wal_path = wal.path().to_path_buf();
+            let wal_len = wal.size().unwrap();
+            drop(wal);
+            let file = OpenOptions::new().write(true).open(wal_path).unwrap();
+            file.set_len(wal_len - 1).unwrap();
+
+            server.start_rsw_mlw_smw_rhw_msr(&Timing::default())
+                  .expect("Server failed to start");
+
+            assert_eq!(server.member_list.health_of_by_id_mlr(&peer.id),
+                       Some(Health::Alive));
+            for service_group in &["witcher.prod", "witcher.dev", "witcher.qa"] {
+                assert!(server.service_store
+                              .lock_rsr()
+                              .service_group(service_group)
+                              .contains_id(&peer.id));
+            }
+            assert!(!server.departure_store.lock_rsr().contains_rumor(&departure));
+        }
+
+        #[test]
+        fn expire_times_out_suspect_members_once_the_given_time_is_past_the_timeout() {
+            let server = start_server();
+            let timing = Timing::default();
+            let suspect = Member::default();
+            let inserted_at = SteadyTime::now();
+            server.member_list
+                  .insert_mlw(suspect.clone(), Health::Suspect);
+
+            expire::run_once(&server, &timing, inserted_at);
+            assert_eq!(server.member_list.health_of_mlr(&suspect),
+                       Some(Health::Suspect));
+
+            let past_timeout =
+                inserted_at + timing.suspicion_timeout_duration() + Duration::seconds(1);
+            expire::run_once(&server, &timing, past_timeout);
+            assert_eq!(server.member_list.health_of_mlr(&suspect),
+                       Some(Health::Confirmed));
+            assert!(server.rumor_heat
+                          .lock_rhr()
+                          .currently_hot_rumors("any-peer")
+                          .contains(&RumorKey::new(RumorType::Member, &suspect.id, "")));
+        }
+
+        #[test]
+        fn expire_times_out_members_matching_an_override_on_their_own_schedule() {
+            let server = start_server();
+            let overrides = vec![TimeoutOverride { selector:
+                                                       MemberSelector::Label(ZONE_LABEL.to_string(),
+                                                                             "remote".to_string()),
+                                                   suspicion_multiplier: 10.0,
+                                                   departure_multiplier: 1.0, }];
+            let timing = Timing { timeout_overrides: overrides,
+                                  ..Timing::default() };
+            let local = Member::default();
+            let mut remote = Member::default();
+            remote.labels
+                  .insert(ZONE_LABEL.to_string(), "remote".to_string());
+            let inserted_at = SteadyTime::now();
+            server.member_list
+                  .insert_mlw(local.clone(), Health::Suspect);
+            server.member_list
+                  .insert_mlw(remote.clone(), Health::Suspect);
+
+            let past_timeout =
+                inserted_at + timing.suspicion_timeout_duration() + Duration::seconds(1);
+            expire::run_once(&server, &timing, past_timeout);
+            assert_eq!(server.member_list.health_of_mlr(&local),
+                       Some(Health::Confirmed));
+            assert_eq!(server.member_list.health_of_mlr(&remote),
+                       Some(Health::Suspect));
+
+            let past_remote_timeout =
+                inserted_at + timing.suspicion_timeout_duration() * 10 + Duration::seconds(1);
+            expire::run_once(&server, &timing, past_remote_timeout);
+            assert_eq!(server.member_list.health_of_mlr(&remote),
+                       Some(Health::Confirmed));
+        }
+
+        /// `body` as a file in `group.default`, split into chunks of 4 bytes.
+        fn service_file_chunks(filename: &str, body: &[u8]) -> Vec<ServiceFile> {
+            ServiceFile::new("uploader",
+                             ServiceGroup::from_str("group.default").unwrap(),
+                             filename,
+                             body.to_vec()).into_chunks(body.len(), 4)
+                                           .expect("service file chunked")
+        }
+
+        fn has_service_file(server: &Server, id: &str) -> bool {
+            server.service_file_store
+                  .lock_rsr()
+                  .service_group("group.default")
+                  .contains_id(id)
+        }
+
+        #[test]
+        fn service_files_over_the_limit_are_rejected() {
+            let mut server = start_server();
+            server.service_file_limit = 8;
+
+            let mut files = service_file_chunks("small", b"01234567");
+            files.extend(service_file_chunks("large", b"012345678"));
+            for file in files {
+                server.insert_service_file_rsw_rhw(file);
+            }
+
+            assert!(has_service_file(&server, "small#0"));
+            assert!(has_service_file(&server, "small#1"));
+            assert!(!has_service_file(&server, "large#0"));
+            assert!(!has_service_file(&server, "large#1"));
+            assert!(!has_service_file(&server, "large#2"));
+        }
+
+        #[test]
+        fn service_files_over_512_kib_are_rejected_by_default() {
+            let server = start_server();
+            let service_group = ServiceGroup::from_str("group.default").unwrap();
+            for &(filename, size) in &[("fits", 512 * 1024), ("oversized", 512 * 1024 + 1)] {
+                let file =
+                    ServiceFile::new("uploader", service_group.clone(), filename, vec![0; size]);
+                server.insert_service_file_rsw_rhw(file);
+            }
+
+            assert!(has_service_file(&server, "fits"));
+            assert!(!has_service_file(&server, "oversized"));
+        }
+
+        #[test]
+        fn a_complete_version_of_a_service_file_replaces_the_chunks_of_older_ones() {
+            let server = start_server();
+            for chunk in service_file_chunks("file", b"0123456789") {
+                server.insert_service_file_rsw_rhw(chunk);
+            }
+
+            let mut newer = service_file_chunks("file", b"abcdefgh");
+            for chunk in newer.iter_mut() {
+                chunk.incarnation = 1;
+            }
+            let last = newer.pop().unwrap();
+            for chunk in newer {
+                server.insert_service_file_rsw_rhw(chunk);
+            }
+            // The older version's chunks stay until the newer version is complete
+            assert!(has_service_file(&server, "file#2"));
+
+            server.insert_service_file_rsw_rhw(last);
+            assert!(has_service_file(&server, "file#0"));
+            assert!(has_service_file(&server, "file#1"));
+            assert!(!has_service_file(&server, "file#2"));
+            assert!(server.service_file_chunks
+                          .lock()
+                          .expect("Service file chunk sets lock is poisoned")
+                          .is_empty());
+
+            let mut whole = ServiceFile::new("uploader",
+                                             ServiceGroup::from_str("group.default").unwrap(),
+                                             "file",
+                                             b"whole".to_vec());
+            whole.incarnation = 2;
+            server.insert_service_file_rsw_rhw(whole);
+            assert!(has_service_file(&server, "file"));
+            assert!(!has_service_file(&server, "file#0"));
+            assert!(!has_service_file(&server, "file#1"));
+        }
+
+        #[test]
+        fn expire_purges_service_file_chunks_whose_set_is_never_completed() {
+            let server = start_server();
+            let timing = Timing::default();
+            let inserted_at = SteadyTime::now();
+            let mut chunks = service_file_chunks("incomplete", b"0123456789");
+            chunks.pop();
+            chunks.extend(service_file_chunks("complete", b"0123456789"));
+            for chunk in chunks {
+                server.insert_service_file_rsw_rhw(chunk);
+            }
+
+            expire::run_once(&server, &timing, inserted_at);
+            assert!(has_service_file(&server, "incomplete#0"));
+            assert!(has_service_file(&server, "incomplete#1"));
+
+            let past_timeout =
+                inserted_at + timing.service_file_chunk_timeout_duration() + Duration::seconds(1);
+            expire::run_once(&server, &timing, past_timeout);
+            assert!(!has_service_file(&server, "incomplete#0"));
+            assert!(!has_service_file(&server, "incomplete#1"));
+            assert!(!server.rumor_heat
+                           .lock_rhr()
+                           .currently_hot_rumors("any-peer")
+                           .contains(&RumorKey::new(RumorType::ServiceFile,
+                                                    "incomplete#0",
+                                                    "group.default")));
+            for id in &["complete#0", "complete#1", "complete#2"] {
+                assert!(has_service_file(&server, id));
+            }
+        }
+
+        #[test]
+        fn inserted_rumors_are_logged_until_the_dat_file_is_written() {
+            let tmpdir = TempDir::new().unwrap();
+            let mut server = new_server(Member::default(), Some(tmpdir.path()));
+            server.start_rsw_mlw_smw_rhw_msr(&Timing::default())
+                  .expect("Server failed to start");
+            let dat_path = tmpdir.path().join(format!("{}.rst", server.member_id()));
+            let wal = Arc::new(DatFileWal::open(&dat_path).expect("wal opened"));
+            server.dat_file_wal = Some(wal.clone());
+
+            let departure = Departure::new("departed-member");
+            server.insert_departure_rsw_mlw_rhw(departure.clone());
+            // Inserting the same rumor again changes nothing, so nothing more is logged
+            server.insert_departure_rsw_mlw_rhw(departure);
+            let logged = wal.size().unwrap();
+            assert!(logged > 0);
+
+            server.persist_data_rsr_mlr();
+            assert_eq!(wal.size().unwrap(), 0);
+        }
+
+        #[test]
+        fn journaled_rumors_are_only_written_to_the_dat_file_when_compacted() {
+            let tmpdir = TempDir::new().unwrap();
+            let mut server = new_server(Member::default(), Some(tmpdir.path()));
+            // Not started, so that no persist loop writes the file behind the test's back
+            let dat_path = tmpdir.path().join(format!("{}.rst", server.member_id()));
+            let persistence = DatFilePersistence::new(dat_path.clone(),
+                                                      DatFileConfig::default(),
+                                                      server.member_id(),
+                                                      None);
+            server.set_persistence(Arc::new(persistence));
+            let mut wal = DatFileWal::open(&dat_path).expect("wal opened");
+            wal.set_compact_size(Some(1024 * 1024));
+            let wal = Arc::new(wal);
+            server.dat_file_wal = Some(wal.clone());
+            server.compact_rsr_mlr();
+            let dat_file_len = fs::metadata(&dat_path).unwrap().len();
+
+            server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+            let logged = wal.size().unwrap();
+            assert!(logged > 0);
+
+            // The log is well under the compact size, so the change stays in it
+            server.persist_data_rsr_mlr();
+            assert_eq!(wal.size().unwrap(), logged);
+            assert_eq!(fs::metadata(&dat_path).unwrap().len(), dat_file_len);
+
+            server.compact_rsr_mlr();
+            assert_eq!(wal.size().unwrap(), 0);
+            assert!(fs::metadata(&dat_path).unwrap().len() > dat_file_len);
+        }
+
+        #[test]
+        fn rumors_missing_from_the_journal_are_written_to_the_dat_file() {
+            let tmpdir = TempDir::new().unwrap();
+            let mut server = new_server(Member::default(), Some(tmpdir.path()));
+            // Not started, so that no persist loop writes the file behind the test's back
+            let dat_path = tmpdir.path().join(format!("{}.rst", server.member_id()));
+            let persistence = DatFilePersistence::new(dat_path.clone(),
+                                                      DatFileConfig::default(),
+                                                      server.member_id(),
+                                                      None);
+            server.set_persistence(Arc::new(persistence));
+            let mut wal = DatFileWal::open(&dat_path).expect("wal opened");
+            wal.set_compact_size(Some(1024 * 1024));
+            let wal = Arc::new(wal);
+            server.persist_data_rsr_mlr();
+            let dat_file_len = fs::metadata(&dat_path).unwrap().len();
+
+            // As if appending it to the log had failed
+            server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+            wal.mark_missed();
+            server.dat_file_wal = Some(wal.clone());
+
+            server.persist_data_rsr_mlr();
+            assert!(fs::metadata(&dat_path).unwrap().len() > dat_file_len);
+            assert!(!wal.needs_compaction().unwrap());
+        }
+
+        #[test]
+        fn rumors_are_restored_from_the_persistence_set_before_starting() {
+            let persistence = Arc::new(MemoryPersistence::default());
+            let mut server = start_server();
+            server.set_persistence(persistence.clone());
+            let departure = Departure::new("departed-member");
+            server.insert_departure_rsw_mlw_rhw(departure.clone());
+            server.persist_data_rsr_mlr();
+            assert!(!persistence.is_empty());
+
+            let mut restarted = start_server();
+            restarted.set_persistence(persistence);
+            restarted.start_rsw_mlw_smw_rhw_msr(&Timing::default())
+                     .expect("Server failed to start");
+            assert!(restarted.departure_store
+                             .lock_rsr()
+                             .contains_rumor(&departure));
+        }
+
+        /// Inserts a departure into `server` as it persists, which would deadlock if the
+        /// server's locks were still held.
+        #[derive(Debug)]
+        struct InsertingPersistence(Server);
+
+        impl RumorPersistence for InsertingPersistence {
+            fn read_snapshot_rsw_mlw_rhw_msr(&self, _server: &Server) -> Result<()> { Ok(()) }
+
+            fn write_snapshot(&self, snapshot: &RumorSnapshot) -> Result<()> {
+                assert_eq!(snapshot.departures.len(), 1);
+                self.0
+                    .insert_departure_rsw_mlw_rhw(Departure::new("while-persisting"));
+                Ok(())
+            }
+        }
+
+        impl fmt::Display for InsertingPersistence {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "inserting") }
+        }
+
+        #[test]
+        fn rumors_can_be_inserted_while_they_are_persisted() {
+            let mut server = start_server();
+            server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+            server.set_persistence(Arc::new(InsertingPersistence(server.clone())));
+            server.compact_rsr_mlr();
+            assert_eq!(server.departure_store.lock_rsr().rumors().count(), 2);
+        }
+
+        #[test]
+        fn persisting_is_due_once_the_interval_has_passed() {
+            let config = PersistConfig::default();
+            assert!(!persist_due(&config, config.interval / 2, 1000));
+            assert!(persist_due(&config, config.interval, 0));
+        }
+
+        #[test]
+        fn persisting_is_due_early_after_enough_changes() {
+            let config = PersistConfig { after_changes: Some(10),
+                                         ..PersistConfig::default() };
+            assert!(!persist_due(&config, config.interval / 2, 9));
+            assert!(persist_due(&config, config.interval / 2, 10));
+        }
+
+        #[test]
+        fn persist_config_from_env_ignores_a_zero_interval() {
+            let lock = lock_persist_loop_period();
+            lock.set("0");
+            assert_eq!(PersistConfig::from_env().interval,
+                       PersistConfig::default().interval);
+            lock.set("5");
+            assert_eq!(PersistConfig::from_env().interval.as_secs(), 5);
+        }
+
+        #[test]
+        fn persist_config_from_env_parses_no_persist_on_shutdown_as_a_bool() {
+            let lock = lock_no_persist_on_shutdown();
+            lock.set("false");
+            assert!(PersistConfig::from_env().on_shutdown);
+            lock.set("true");
+            assert!(!PersistConfig::from_env().on_shutdown);
+            lock.unset();
+            assert!(PersistConfig::from_env().on_shutdown);
+        }
+
+        #[test]
+        fn the_update_counter_counts_changes_to_every_store() {
+            let server = start_server();
+            let before = server.update_counter();
+            server.insert_member_mlw_rhw(Member::default(), Health::Alive);
+            server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+            assert!(server.update_counter().wrapping_sub(before) >= 2);
+        }
+
+        #[test]
+        fn rumors_are_only_persisted_on_shutdown_when_configured() {
+            for &on_shutdown in &[true, false] {
+                let persistence = Arc::new(MemoryPersistence::default());
+                let mut server = start_server();
+                server.set_persistence(persistence.clone());
+                server.set_persist_config(PersistConfig { on_shutdown,
+                                                          ..PersistConfig::default() });
+                server.insert_departure_rsw_mlw_rhw(Departure::new("departed-member"));
+                server.persist_on_shutdown_rsr_mlr();
+                assert_eq!(persistence.is_empty(), !on_shutdown);
+            }
+        }
     }
 }
@@ -1,14 +1,17 @@
 //! Periodically check membership rumors to automatically "time out"
 //! `Suspect` rumors to `Confirmed`, and `Confirmed` rumors to
-//! `Departed`.
+//! `Departed`, purge service file chunks whose set was never completed, and stop tracking
+//! the propagation of old departures.
 
-use crate::{rumor::{RumorKey,
+use crate::{member::Member,
+            rumor::{RumorKey,
                     RumorType},
             server::{timing::Timing,
                      Server}};
 use habitat_common::liveliness_checker;
 use std::{thread,
           time::Duration};
+use time::SteadyTime;
 
 const LOOP_DELAY_MS: u64 = 500;
 
@@ -22,27 +25,49 @@ fn run_loop(server: &Server, timing: &Timing) -> ! {
     loop {
         liveliness_checker::mark_thread_alive().and_divergent();
 
-        let newly_confirmed_members =
-            server.member_list
-                  .members_expired_to_confirmed_mlw(timing.suspicion_timeout_duration());
+        run_once(server, timing, SteadyTime::now());
 
-        for id in newly_confirmed_members {
-            server.rumor_heat
-                  .lock_rhw()
-                  .start_hot_rumor(RumorKey::new(RumorType::Member, &id, ""));
-        }
+        thread::sleep(Duration::from_millis(LOOP_DELAY_MS));
+    }
+}
 
-        let newly_departed_members =
-            server.member_list
-                  .members_expired_to_departed_mlw(timing.departure_timeout_duration());
+/// Time out the members whose suspicion or departure timeouts have passed as of `now`, and start
+/// gossiping their new health. Orphaned service file chunks and old departure witnesses are
+/// purged on the same pass. Taking
+/// `now` as an argument lets tests check expiration without waiting out the timeouts.
+///
+/// # Locking (see locking.md)
+/// * `MemberList::entries` (write)
+/// * `RumorHeat::inner` (write)
+/// * `RumorStore::list` (write)
+pub(super) fn run_once(server: &Server, timing: &Timing, now: SteadyTime) {
+    trace!("Expiring members; members by health: {:?}",
+           server.member_list.health_counts_mlr());
 
-        for id in newly_departed_members {
-            server.rumor_heat.lock_rhw().purge(&id);
-            server.rumor_heat
-                  .lock_rhw()
-                  .start_hot_rumor(RumorKey::new(RumorType::Member, &id, ""));
-        }
+    let suspicion_timeout = |member: &Member| timing.suspicion_timeout_duration_for(member);
+    let newly_confirmed_members =
+        server.member_list
+              .members_expired_to_confirmed_per_member_mlw(suspicion_timeout, now);
 
-        thread::sleep(Duration::from_millis(LOOP_DELAY_MS));
+    for id in newly_confirmed_members {
+        server.rumor_heat
+              .lock_rhw()
+              .start_hot_rumor(RumorKey::new(RumorType::Member, &id, ""));
     }
+
+    let departure_timeout = |member: &Member| timing.departure_timeout_duration_for(member);
+    let newly_departed_members =
+        server.member_list
+              .members_expired_to_departed_per_member_mlw(departure_timeout, now);
+
+    for id in newly_departed_members {
+        server.rumor_heat.lock_rhw().purge(&id);
+        server.rumor_heat
+              .lock_rhw()
+              .start_hot_rumor(RumorKey::new(RumorType::Member, &id, ""));
+    }
+
+    server.purge_orphaned_service_file_chunks_rsw_rhw(timing.service_file_chunk_timeout_duration(),
+                                                      now);
+    server.purge_departure_witnesses(timing.departure_witness_timeout_duration(), now);
 }
@@ -209,7 +209,7 @@ fn process_ack_mlw_smw_rhw(server: &Server,
     match tx_outbound.send((addr, msg)) {
         Ok(()) => {
             for membership in memberships {
-                server.insert_member_from_rumor_mlw_smw_rhw(membership.member, membership.health);
+                server.insert_member_from_rumor_mlw_smw_rhw(membership);
             }
         }
         Err(e) => panic!("Outbound thread has died - this shouldn't happen: #{:?}", e),
@@ -231,6 +231,6 @@ fn process_ping_mlw_smw_rhw(server: &Server, socket: &UdpSocket, addr: SocketAdd
         server.insert_member_mlw_rhw(msg.from, Health::Alive);
     }
     for membership in msg.membership {
-        server.insert_member_from_rumor_mlw_smw_rhw(membership.member, membership.health);
+        server.insert_member_from_rumor_mlw_smw_rhw(membership);
     }
 }
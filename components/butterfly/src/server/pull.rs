@@ -5,6 +5,7 @@
 use crate::{rumor::{RumorEnvelope,
                     RumorKind},
             server::Server,
+            MaxInboundMessageBytes,
             ZMQ_CONTEXT};
 use habitat_common::liveliness_checker;
 use habitat_core::util::ToI64;
@@ -43,6 +44,10 @@ fn run_loop(server: &Server) -> ! {
           .expect("Failure to set the ZMQ Pull socket to not use keepalive");
     socket.set_rcvtimeo(RecvTimeoutMillis::configured_value().into())
           .expect("Failure to set the ZMQ Pull socket receive timeout");
+    // ZMQ drops the connection of a peer that sends a larger message, before reading its body.
+    let max_message_bytes: usize = MaxInboundMessageBytes::configured_value().into();
+    socket.set_maxmsgsize(max_message_bytes.to_i64())
+          .expect("Failure to set the ZMQ Pull socket maximum message size");
     socket.bind(&format!("tcp://{}", server.gossip_addr()))
           .expect("Failure to bind the ZMQ Pull socket to the port");
     'recv: loop {
@@ -114,7 +119,7 @@ fn run_loop(server: &Server) -> ! {
 
         match proto.kind {
             RumorKind::Membership(membership) => {
-                server.insert_member_from_rumor_mlw_smw_rhw(membership.member, membership.health);
+                server.insert_member_from_rumor_mlw_smw_rhw(membership);
             }
             RumorKind::Service(service) => server.insert_service_rsw_mlw_rhw(*service),
             RumorKind::ServiceConfig(service_config) => {
@@ -130,7 +135,7 @@ fn run_loop(server: &Server) -> ! {
                 server.insert_update_election_rsw_mlr_rhw(election);
             }
             RumorKind::Departure(departure) => {
-                server.insert_departure_rsw_mlw_rhw(departure);
+                server.insert_departure_from_peer_rsw_mlw_rhw(departure, &proto.from_id);
             }
         }
     }
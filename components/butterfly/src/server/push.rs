@@ -3,8 +3,7 @@
 //! This is the thread for distributing rumors to members. It distributes to `FANOUT` members, no
 //! more often than `Timing::GOSSIP_PERIOD_DEFAULT_MS`.
 
-use crate::{member::{Member,
-                     Membership},
+use crate::{member::Member,
             rumor::{RumorEnvelope,
                     RumorKey,
                     RumorKind,
@@ -21,7 +20,7 @@ use std::{thread,
 use time::SteadyTime;
 use zmq;
 
-const FANOUT: usize = 5;
+pub(super) const FANOUT: usize = 5;
 
 lazy_static! {
     static ref GOSSIP_MESSAGES_SENT: IntCounterVec =
@@ -40,10 +39,10 @@ pub fn spawn_thread(name: String, server: Server, timing: Timing) -> std::io::Re
                           .map(|_| ())
 }
 
-/// Executes the Push thread. Gets a list of members to talk to that are not Confirmed; then
-/// proceeds to process the list in `FANOUT` sized chunks. If we finish sending the messages to
-/// all FANOUT targets faster than `Timing::GOSSIP_PERIOD_DEFAULT_MS`, we will block until we
-/// exceed that time.
+/// Executes the Push thread. Gets a list of members to talk to that are not Confirmed (see
+/// `Server::gossip_peers_for_round_mlr_smr`); then proceeds to process the list in `FANOUT` sized
+/// chunks. If we finish sending the messages to all FANOUT targets faster than
+/// `Timing::GOSSIP_PERIOD_DEFAULT_MS`, we will block until we exceed that time.
 fn run_loop(server: &Server, timing: &Timing) -> ! {
     loop {
         liveliness_checker::mark_thread_alive().and_divergent();
@@ -55,7 +54,7 @@ fn run_loop(server: &Server, timing: &Timing) -> ! {
 
         server.update_gossip_round();
 
-        let mut check_list = server.member_list.check_list_mlr(server.member_id());
+        let mut check_list = server.gossip_peers_for_round_mlr_smr();
         let long_wait = timing.gossip_timeout();
 
         'fanout: loop {
@@ -297,6 +296,9 @@ fn send_rumors_rsr_mlr_rhw(server: &Server, member: &Member, rumors: &[RumorKey]
         };
         match socket.send(&payload, 0) {
             Ok(()) => {
+                if rumor_key.kind == RumorType::Departure {
+                    server.record_departure_gossiped(&rumor_key.id, &member.id);
+                }
                 GOSSIP_MESSAGES_SENT.with_label_values(&[&rumor_key.kind.to_string(), "success"])
                                     .inc();
                 GOSSIP_BYTES_SENT.with_label_values(&[&rumor_key.kind.to_string(), "success"])
@@ -320,11 +322,8 @@ fn send_rumors_rsr_mlr_rhw(server: &Server, member: &Member, rumors: &[RumorKey]
 /// # Locking (see locking.md)
 /// * `MemberList::entries` (read)
 fn create_member_rumor_mlr(server: &Server, rumor_key: &RumorKey) -> Option<RumorEnvelope> {
-    let member = server.member_list.get_cloned_mlr(&rumor_key.to_string())?;
-    let payload = Membership { member,
-                               health: server.member_list
-                                             .health_of_by_id_mlr(&rumor_key.to_string())
-                                             .unwrap() };
+    let payload = server.member_list
+                        .membership_for_mlr(&rumor_key.to_string())?;
     let rumor = RumorEnvelope { r#type:  RumorType::Member,
                                 from_id: server.member_id().to_string(),
                                 kind:    RumorKind::Membership(payload), };
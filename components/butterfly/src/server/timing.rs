@@ -1,3 +1,7 @@
+use crate::{error::{Error,
+                    Result},
+            member::Member};
+use std::str::FromStr;
 use time::{Duration as TimeDuration,
            SteadyTime};
 
@@ -5,6 +9,8 @@ use time::{Duration as TimeDuration,
 const PING_TIMING_DEFAULT_MS: i64 = 1000;
 /// How long to wait for an Ack after we PingReq - should be at least 2x the PING_TIMING_DEFAULT_MS
 const PINGREQ_TIMING_DEFAULT_MS: i64 = 2100;
+/// How long between the start of one probe of a member and the start of the next
+const PROBE_INTERVAL_DEFAULT_MS: i64 = PING_TIMING_DEFAULT_MS + PINGREQ_TIMING_DEFAULT_MS;
 /// How many protocol periods before a suspect member is marked as confirmed.
 const SUSPICION_TIMEOUT_DEFAULT_PROTOCOL_PERIODS: i64 = 3;
 /// How long is the gossip period
@@ -12,29 +18,125 @@ const GOSSIP_PERIOD_DEFAULT_MS: i64 = 1000;
 /// How long before we set a confirmed member to a departed member, removing them from quorums
 ///   just for your own sanity - this is 3 days.
 const DEPARTURE_TIMEOUT_DEFAULT_MS: i64 = 259_200_000;
+/// How long the chunks of a service file can wait for the rest of their set before they are
+/// purged - 10 minutes.
+const SERVICE_FILE_CHUNK_TIMEOUT_DEFAULT_MS: i64 = 600_000;
+/// How long a departure's propagation is tracked after this server first gossips it or hears
+/// it confirmed - 10 minutes.
+const DEPARTURE_WITNESS_TIMEOUT_DEFAULT_MS: i64 = 600_000;
+
+/// Which members a `TimeoutOverride` applies to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemberSelector {
+    /// Members with the label, such as `zone`, set to the value
+    Label(String, String),
+    /// Members whose address starts with the prefix
+    AddressPrefix(String),
+}
+
+impl MemberSelector {
+    pub fn matches(&self, member: &Member) -> bool {
+        match self {
+            MemberSelector::Label(label, value) => member.labels.get(label) == Some(value),
+            MemberSelector::AddressPrefix(prefix) => member.address.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Scales the suspicion and departure timeouts of the members a selector matches, so that
+/// members across a slow link can be given longer to answer than members close by.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimeoutOverride {
+    pub selector:             MemberSelector,
+    pub suspicion_multiplier: f64,
+    pub departure_multiplier: f64,
+}
+
+impl TimeoutOverride {
+    /// An override scaling timeouts by the multipliers, which must be finite and greater than 0.
+    pub fn new(selector: MemberSelector,
+               suspicion_multiplier: f64,
+               departure_multiplier: f64)
+               -> Result<TimeoutOverride> {
+        for multiplier in &[suspicion_multiplier, departure_multiplier] {
+            if !(multiplier.is_finite() && *multiplier > 0.0) {
+                return Err(Error::InvalidTimeoutOverride(multiplier.to_string(),
+                                                         "multipliers must be \
+                                                          greater than 0"));
+            }
+        }
+        Ok(TimeoutOverride { selector,
+                             suspicion_multiplier,
+                             departure_multiplier })
+    }
+}
+
+impl FromStr for TimeoutOverride {
+    type Err = Error;
+
+    /// Parses `<selector>,<suspicion multiplier>,<departure multiplier>`, where the selector is
+    /// either `label:<label>=<value>` or `address:<prefix>`. For example,
+    /// `label:zone=eu-west,3,2` triples the suspicion timeout and doubles the departure timeout
+    /// of members in the `eu-west` zone.
+    fn from_str(value: &str) -> Result<TimeoutOverride> {
+        let invalid = |reason| Error::InvalidTimeoutOverride(value.to_string(), reason);
+        let mut parts = value.rsplitn(3, ',');
+        let departure = parts.next().and_then(|m| m.trim().parse().ok());
+        let suspicion = parts.next().and_then(|m| m.trim().parse().ok());
+        let (suspicion, departure) = match (suspicion, departure) {
+            (Some(suspicion), Some(departure)) => (suspicion, departure),
+            _ => return Err(invalid("expected <selector>,<suspicion>,<departure>")),
+        };
+        let selector = parts.next().unwrap_or_default();
+        let selector = if selector.starts_with("label:") {
+            let mut label = selector["label:".len()..].splitn(2, '=');
+            match (label.next(), label.next()) {
+                (Some(name), Some(value)) if !name.is_empty() => {
+                    MemberSelector::Label(name.to_string(), value.to_string())
+                }
+                _ => return Err(invalid("expected a label selector of label:<label>=<value>")),
+            }
+        } else if selector.starts_with("address:") {
+            MemberSelector::AddressPrefix(selector["address:".len()..].to_string())
+        } else {
+            return Err(invalid("the selector must start with label: or address:"));
+        };
+        TimeoutOverride::new(selector, suspicion, departure)
+    }
+}
 
 /// The timing of the outbound threads.
 #[derive(Debug, Clone)]
 pub struct Timing {
     pub ping_ms: i64,
     pub pingreq_ms: i64,
+    pub probe_interval_ms: i64,
     pub gossip_period_ms: i64,
     pub suspicion_timeout_protocol_periods: i64,
     pub departure_timeout_ms: i64,
+    pub service_file_chunk_timeout_ms: i64,
+    pub departure_witness_timeout_ms: i64,
+    /// Checked in order; the first override that matches a member applies to it. Members no
+    /// override matches use the global timeouts.
+    pub timeout_overrides: Vec<TimeoutOverride>,
 }
 
 impl Default for Timing {
     fn default() -> Timing {
         Timing { ping_ms: PING_TIMING_DEFAULT_MS,
                  pingreq_ms: PINGREQ_TIMING_DEFAULT_MS,
+                 probe_interval_ms: PROBE_INTERVAL_DEFAULT_MS,
                  gossip_period_ms: GOSSIP_PERIOD_DEFAULT_MS,
                  suspicion_timeout_protocol_periods: SUSPICION_TIMEOUT_DEFAULT_PROTOCOL_PERIODS,
-                 departure_timeout_ms: DEPARTURE_TIMEOUT_DEFAULT_MS, }
+                 departure_timeout_ms: DEPARTURE_TIMEOUT_DEFAULT_MS,
+                 service_file_chunk_timeout_ms: SERVICE_FILE_CHUNK_TIMEOUT_DEFAULT_MS,
+                 departure_witness_timeout_ms: DEPARTURE_WITNESS_TIMEOUT_DEFAULT_MS,
+                 timeout_overrides: Vec::new(), }
     }
 }
 
 impl Timing {
-    /// Set up a new Timing
+    /// Set up a new Timing, probing members as often as the ping and pingreq timeouts allow
     pub fn new(ping_ms: i64,
                pingreq_ms: i64,
                gossip_period_ms: i64,
@@ -43,9 +145,13 @@ impl Timing {
                -> Timing {
         Timing { ping_ms,
                  pingreq_ms,
+                 probe_interval_ms: ping_ms + pingreq_ms,
                  gossip_period_ms,
                  suspicion_timeout_protocol_periods,
-                 departure_timeout_ms }
+                 departure_timeout_ms,
+                 service_file_chunk_timeout_ms: SERVICE_FILE_CHUNK_TIMEOUT_DEFAULT_MS,
+                 departure_witness_timeout_ms: DEPARTURE_WITNESS_TIMEOUT_DEFAULT_MS,
+                 timeout_overrides: Vec::new() }
     }
 
     /// When should this gossip period expire
@@ -53,8 +159,9 @@ impl Timing {
         SteadyTime::now() + TimeDuration::milliseconds(self.gossip_period_ms)
     }
 
-    /// How long is a protocol period, in millis.
-    pub fn protocol_period_ms(&self) -> i64 { self.ping_ms + self.pingreq_ms }
+    /// How long is a protocol period, in millis. This is the probe interval, so suspicion timeouts
+    /// stretch and shrink along with it.
+    pub fn protocol_period_ms(&self) -> i64 { self.probe_interval_ms }
 
     /// When should this ping record time out?
     pub fn ping_timeout(&self) -> SteadyTime {
@@ -68,7 +175,7 @@ impl Timing {
 
     /// How long before the next scheduled protocol period
     pub fn next_protocol_period(&self) -> SteadyTime {
-        SteadyTime::now() + TimeDuration::milliseconds(self.ping_ms + self.pingreq_ms)
+        SteadyTime::now() + TimeDuration::milliseconds(self.probe_interval_ms)
     }
 
     /// How long before this suspect entry times out
@@ -80,4 +187,90 @@ impl Timing {
     pub fn departure_timeout_duration(&self) -> TimeDuration {
         TimeDuration::milliseconds(self.departure_timeout_ms)
     }
+
+    /// How long before this suspect member times out, taking overrides into account
+    pub fn suspicion_timeout_duration_for(&self, member: &Member) -> TimeDuration {
+        let timeout = self.suspicion_timeout_duration();
+        match self.timeout_override_for(member) {
+            Some(o) => scale(timeout, o.suspicion_multiplier),
+            None => timeout,
+        }
+    }
+
+    /// How long before this confirmed member departs, taking overrides into account
+    pub fn departure_timeout_duration_for(&self, member: &Member) -> TimeDuration {
+        let timeout = self.departure_timeout_duration();
+        match self.timeout_override_for(member) {
+            Some(o) => scale(timeout, o.departure_multiplier),
+            None => timeout,
+        }
+    }
+
+    fn timeout_override_for(&self, member: &Member) -> Option<&TimeoutOverride> {
+        self.timeout_overrides
+            .iter()
+            .find(|o| o.selector.matches(member))
+    }
+
+    /// How long before an incomplete set of service file chunks is purged
+    pub fn service_file_chunk_timeout_duration(&self) -> TimeDuration {
+        TimeDuration::milliseconds(self.service_file_chunk_timeout_ms)
+    }
+
+    /// How long before a departure's propagation stops being tracked
+    pub fn departure_witness_timeout_duration(&self) -> TimeDuration {
+        TimeDuration::milliseconds(self.departure_witness_timeout_ms)
+    }
+}
+
+/// The multiplier is expected to be finite and positive, as `TimeoutOverride::new` checks.
+fn scale(timeout: TimeDuration, multiplier: f64) -> TimeDuration {
+    TimeDuration::milliseconds((timeout.num_milliseconds() as f64 * multiplier) as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timeout_overrides_parse_label_and_address_selectors() {
+        let label: TimeoutOverride = "label:zone=eu-west,3,2".parse().unwrap();
+        assert_eq!(label.selector,
+                   MemberSelector::Label("zone".to_string(), "eu-west".to_string()));
+        assert_eq!(label.suspicion_multiplier, 3.0);
+        assert_eq!(label.departure_multiplier, 2.0);
+
+        let address: TimeoutOverride = "address:10.1.,0.5,1".parse().unwrap();
+        assert_eq!(address.selector,
+                   MemberSelector::AddressPrefix("10.1.".to_string()));
+        assert_eq!(address.suspicion_multiplier, 0.5);
+    }
+
+    #[test]
+    fn timeout_overrides_reject_multipliers_that_are_not_positive() {
+        for value in &["label:zone=eu-west,0,2",
+                       "label:zone=eu-west,3,-1",
+                       "label:zone=eu-west,NaN,2",
+                       "label:zone=eu-west,3,inf"]
+        {
+            assert!(value.parse::<TimeoutOverride>().is_err(),
+                    "{} was accepted",
+                    value);
+        }
+        assert!(TimeoutOverride::new(MemberSelector::AddressPrefix("10.".to_string()), -1.0, 1.0)
+                .is_err());
+    }
+
+    #[test]
+    fn timeout_overrides_reject_malformed_selectors() {
+        for value in &["zone=eu-west,3,2",
+                       "label:zone,3,2",
+                       "label:=eu-west,3,2",
+                       "3,2"]
+        {
+            assert!(value.parse::<TimeoutOverride>().is_err(),
+                    "{} was accepted",
+                    value);
+        }
+    }
 }
@@ -7,7 +7,8 @@ use crate::{error::{Error,
                      Membership},
             protocol::{self,
                        swim as proto,
-                       FromProto}};
+                       FromProto,
+                       Message}};
 use bytes::BytesMut;
 use prost::Message as ProstMessage;
 use std::{fmt,
@@ -261,6 +262,9 @@ pub struct Swim {
 }
 
 impl Swim {
+    /// The tag of the repeated `membership` field of the `Swim` message in swim.proto.
+    const MEMBERSHIP_TAG: u32 = 5;
+
     pub fn decode(bytes: &[u8]) -> Result<Self> {
         let proto = proto::Swim::decode(bytes)?;
         let r#type = SwimType::from_i32(proto.r#type).ok_or(Error::ProtocolMismatch("type"))?;
@@ -268,6 +272,10 @@ impl Swim {
         for membership in proto.membership.clone() {
             memberships.push(Membership::from_proto(membership)?);
         }
+        let encoded_memberships = protocol::length_delimited_fields(bytes, Self::MEMBERSHIP_TAG)?;
+        for (membership, encoded) in memberships.iter_mut().zip(encoded_memberships) {
+            membership.restore_unknown_fields(encoded)?;
+        }
         let kind = match r#type {
             SwimType::Ack => SwimKind::Ack(Ack::from_proto(proto)?),
             SwimType::Ping => SwimKind::Ping(Ping::from_proto(proto)?),
@@ -278,11 +286,22 @@ impl Swim {
                   kind })
     }
 
+    /// Prost can't encode the unknown fields of the piggy-backed memberships, so they are
+    /// appended by hand.
     pub fn encode(self) -> Result<Vec<u8>> {
-        let proto: proto::Swim = self.into();
+        let mut memberships = Vec::with_capacity(self.membership.len());
+        for membership in &self.membership {
+            memberships.push(membership.write_to_bytes()?);
+        }
+        let mut proto: proto::Swim = self.into();
+        proto.membership.clear();
         let mut buf = BytesMut::with_capacity(proto.encoded_len());
         proto.encode(&mut buf)?;
-        Ok(buf.to_vec())
+        let mut buf = buf.to_vec();
+        for membership in memberships {
+            protocol::append_length_delimited_field(&mut buf, Self::MEMBERSHIP_TAG, &membership);
+        }
+        Ok(buf)
     }
 }
 
@@ -297,3 +316,28 @@ impl From<Swim> for proto::Swim {
     }
 }
 //
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piggy_backed_unknown_fields_survive_a_roundtrip() {
+        let member = Member { unknown_fields: vec![9 << 3, 42],
+                              ..Member::default() };
+        let membership = Membership { member,
+                                      health: Health::Suspect,
+                                      unknown_fields: vec![10 << 3, 7] };
+        let ping = Ping { membership: vec![membership.clone(), membership.clone()],
+                          from:       Member::default(),
+                          forward_to: None, };
+
+        let decoded = Swim::decode(&Swim::from(ping).encode().unwrap()).unwrap();
+        assert_eq!(decoded.membership.len(), 2);
+        for decoded in decoded.membership {
+            assert_eq!(decoded.member, membership.member);
+            assert_eq!(decoded.health, Health::Suspect);
+            assert_eq!(decoded.unknown_fields, membership.unknown_fields);
+        }
+    }
+}
@@ -43,6 +43,17 @@ impl Suitability for NSuitability {
 /// * `Server::member` (write)
 /// * `RumorHeat::inner` (write)
 pub fn start_server_smw_rhw(name: &str, ring_key: Option<SymKey>, suitability: u64) -> Server {
+    start_server_with_timing_smw_rhw(name, ring_key, suitability, &Timing::default())
+}
+
+/// # Locking (see locking.md)
+/// * `Server::member` (write)
+/// * `RumorHeat::inner` (write)
+pub fn start_server_with_timing_smw_rhw(name: &str,
+                                        ring_key: Option<SymKey>,
+                                        suitability: u64,
+                                        timing: &Timing)
+                                        -> Server {
     let swim_port;
     let gossip_port;
     {
@@ -64,7 +75,7 @@ pub fn start_server_smw_rhw(name: &str, ring_key: Option<SymKey>, suitability: u
                                  Some(String::from(name)),
                                  None,
                                  Arc::new(NSuitability(suitability))).unwrap();
-    server.start_rsw_mlw_smw_rhw_msr(&Timing::default())
+    server.start_rsw_mlw_smw_rhw_msr(timing)
           .expect("Cannot start server");
     server
 }
@@ -120,6 +131,18 @@ impl SwimNet {
         SwimNet::new_with_suitability_rhw(suitabilities)
     }
 
+    /// # Locking (see locking.md)
+    /// * `RumorHeat::inner` (write)
+    pub fn new_with_timing_rhw(count: usize, timing: &Timing) -> SwimNet {
+        SwimNet { members: (0..count).map(|x| {
+                                         start_server_with_timing_smw_rhw(&format!("{}", x),
+                                                                          None,
+                                                                          0,
+                                                                          timing)
+                                     })
+                                     .collect(), }
+    }
+
     /// # Locking (see locking.md)
     /// * `RumorHeat::inner` (write)
     pub fn new_ring_encryption_rhw(count: usize, ring_key: &SymKey) -> SwimNet {
@@ -430,11 +453,12 @@ impl SwimNet {
         let ident = PackageIdent::from_str(package).expect("package needs to be a fully \
                                                             qualified package identifier");
         let sg = ServiceGroup::new(None, ident.name(), "prod", None).unwrap();
-        let s = Service::new(self[member].member_id().to_string(),
-                             &ident,
-                             sg,
-                             SysInfo::default(),
-                             None);
+        let mut s = Service::new(self[member].member_id().to_string(),
+                                 &ident,
+                                 sg,
+                                 SysInfo::default(),
+                                 None);
+        s.incarnation = 1;
         self[member].insert_service_rsw_mlw_rhw(s);
     }
 
@@ -5,7 +5,11 @@ mod rumor;
 
 use common as btest;
 use habitat_butterfly::{self,
-                        member::Health};
+                        member::Health,
+                        server::timing::Timing};
+use std::{thread,
+          time::{Duration,
+                 Instant}};
 
 #[test]
 fn two_members_meshed_confirm_one_member() {
@@ -18,6 +22,26 @@ fn two_members_meshed_confirm_one_member() {
     assert_wait_for_health_of_mlr!(net, 1, 0, Health::Confirmed);
 }
 
+#[test]
+fn short_probe_interval_detects_a_stopped_member_quickly() {
+    let timing = Timing { ping_ms: 5,
+                          pingreq_ms: 10,
+                          probe_interval_ms: 10,
+                          ..Timing::default() };
+    let mut net = btest::SwimNet::new_with_timing_rhw(2, &timing);
+    net.mesh_mlw_smr();
+    assert_wait_for_health_of_mlr!(net, 1, 0, Health::Alive);
+    net[0].pause();
+    // The default timing takes over 3 seconds to suspect a member, so this still tells the two
+    // apart without depending on how promptly the probe thread is scheduled.
+    let deadline = Instant::now() + Duration::from_secs(2);
+    while net.health_of_mlr(1, 0) != Some(Health::Suspect) {
+        assert!(Instant::now() < deadline,
+                "Member was not marked Suspect within 2 seconds");
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
 #[test]
 fn six_members_meshed_confirm_one_member() {
     let mut net = btest::SwimNet::new_rhw(6);
@@ -55,7 +55,8 @@ use crate::{api_client::{self,
 use glob;
 use reqwest::StatusCode;
 use retry::{delay,
-            retry};
+            retry,
+            OperationResult};
 
 use crate::{error::{Error,
                     Result},
@@ -296,9 +297,10 @@ impl<'a> fmt::Display for FullyQualifiedPackageIdent<'a> {
 /// If an `InstallSource::Ident` is given, we retrieve the package
 /// from the specified Builder `url`. Providing a fully-qualified
 /// identifer will result in that exact package being installed
-/// (regardless of `channel`). Providing a partially-qualified
+/// (regardless of `channels`). Providing a partially-qualified
 /// identifier will result in the installation of latest appropriate
-/// release from the given `channel`.
+/// release from the first of the given `channels` that has one; the
+/// channels are listed in order of precedence.
 ///
 /// If an `InstallSource::Archive` is given, then this exact artifact will be
 /// installed, instead of retrieving it from Builder.
@@ -315,7 +317,7 @@ impl<'a> fmt::Display for FullyQualifiedPackageIdent<'a> {
 #[allow(clippy::too_many_arguments)]
 pub fn start<U>(ui: &mut U,
                 url: &str,
-                channel: &ChannelIdent,
+                channels: &[ChannelIdent],
                 install_source: &InstallSource,
                 product: &str,
                 version: &str,
@@ -337,7 +339,7 @@ pub fn start<U>(ui: &mut U,
     let task = InstallTask { install_mode,
                              local_package_usage,
                              api_client,
-                             channel,
+                             channels,
                              fs_root_path,
                              artifact_cache_path,
                              key_cache_path,
@@ -420,7 +422,8 @@ struct InstallTask<'a> {
     install_mode: &'a InstallMode,
     local_package_usage: &'a LocalPackageUsage,
     api_client: BoxedClient,
-    channel: &'a ChannelIdent,
+    /// The channels to search for packages, in order of precedence
+    channels: &'a [ChannelIdent],
     fs_root_path: &'a Path,
     /// The path to the local artifact cache (e.g., /hab/cache/artifacts)
     artifact_cache_path: &'a Path,
@@ -435,8 +438,9 @@ impl<'a> InstallTask<'a> {
     /// release will be installed (if it exists on Builder).
     ///
     /// However, if the identifier is _not_ fully-qualified, the
-    /// latest version from the given channel will be installed
-    /// instead, assuming a newer version is not found locally.
+    /// latest version from the first of the given channels that has
+    /// one will be installed instead, assuming a newer version is not
+    /// found locally.
     ///
     /// In either case, the identifier returned will be the
     /// fully-qualified identifier of package that was infstalled
@@ -536,11 +540,27 @@ impl<'a> InstallTask<'a> {
             let latest_local = self.latest_installed_ident(&ident);
 
             ui.status(Status::Determining,
-                      format!("latest version of {} in the '{}' channel",
-                              &ident, self.channel))?;
-            let latest_remote = match self.fetch_latest_pkg_ident_for((&ident, target), token) {
-                Ok(latest_ident) => Some(latest_ident),
-                Err(Error::APIClient(APIError(StatusCode::NOT_FOUND, _))) => None,
+                      format!("latest version of {} in the {}",
+                              &ident,
+                              self.channels_description()))?;
+            let fetched = first_channel_with(self.channels, |channel| {
+                self.fetch_latest_pkg_ident_in_channel_for((&ident, target), channel, token)
+            });
+            let latest_remote = match fetched {
+                Ok(Some((channel, latest_ident))) => {
+                    debug!("{} resolved to {} from the '{}' channel",
+                           &ident,
+                           latest_ident.as_ref(),
+                           channel);
+                    if self.channels.len() > 1 {
+                        ui.status(Status::Found,
+                                  format!("{} in the '{}' channel",
+                                          latest_ident.as_ref(),
+                                          channel))?;
+                    }
+                    Some(latest_ident)
+                }
+                Ok(None) => None,
                 Err(e) => {
                     debug!("error fetching ident: {:?}", e);
                     return Err(e);
@@ -575,10 +595,10 @@ impl<'a> InstallTask<'a> {
                         Err(Error::PackageNotFound("".to_string()))
                     } else {
                         ui.status(Status::Missing,
-                                  format!("remote version of '{}' in the '{}' channel, but an \
-                                           installed version was found locally ({})",
+                                  format!("remote version of '{}' in the {}, but an installed \
+                                           version was found locally ({})",
                                           &ident,
-                                          self.channel,
+                                          self.channels_description(),
                                           local.as_ref()))?;
                         FullyQualifiedPackageIdent::from(local.as_ref().clone())
                     }
@@ -673,17 +693,16 @@ impl<'a> InstallTask<'a> {
                    ident);
         } else if self.is_offline() {
             return Err(Error::OfflineArtifactNotFound(ident.as_ref().clone()));
-        } else if let Err(err) = retry(delay::Fixed::from(RETRY_WAIT).take(RETRIES), fetch_artifact)
-        {
-            return Err(Error::DownloadFailed(format!("We tried {} times but \
-                                                      could not download {}. \
-                                                      Last error was: {}",
-                                                     RETRIES, ident, err)));
+        } else {
+            fetch_with_retries((ident.as_ref(), target),
+                               delay::Fixed::from(RETRY_WAIT).take(RETRIES),
+                               fetch_artifact)?;
         }
 
         let mut artifact = PackageArchive::new(self.cached_artifact_path(ident));
         ui.status(Status::Verifying, artifact.ident()?)?;
-        self.verify_artifact(ui, ident, token, &mut artifact)?;
+        let signer = self.verify_artifact(ui, ident, token, &mut artifact)?;
+        ui.status(Status::Verified, format!("{} signed by {}", ident, signer))?;
         Ok(artifact)
     }
 
@@ -823,13 +842,6 @@ impl<'a> InstallTask<'a> {
         self.artifact_cache_path.join(ident.archive_name())
     }
 
-    fn fetch_latest_pkg_ident_for(&self,
-                                  (ident, target): (&PackageIdent, PackageTarget),
-                                  token: Option<&str>)
-                                  -> Result<FullyQualifiedPackageIdent<'_>> {
-        self.fetch_latest_pkg_ident_in_channel_for((ident, target), &self.channel, token)
-    }
-
     fn fetch_latest_pkg_ident_in_channel_for(&self,
                                              (ident, target): (&PackageIdent, PackageTarget),
                                              channel: &ChannelIdent,
@@ -928,12 +940,14 @@ impl<'a> InstallTask<'a> {
         Ok(())
     }
 
+    /// Verifies the artifact against its origin key, returning the name with revision of the key
+    /// that signed it.
     fn verify_artifact<T>(&self,
                           ui: &mut T,
                           ident: &FullyQualifiedPackageIdent<'_>,
                           token: Option<&str>,
                           artifact: &mut PackageArchive)
-                          -> Result<()>
+                          -> Result<String>
         where T: UIWriter
     {
         let artifact_ident = artifact.ident()?;
@@ -963,11 +977,26 @@ impl<'a> InstallTask<'a> {
 
         artifact.verify(&self.key_cache_path)?;
         debug!("Verified {} signed by {}", ident, &nwr);
-        Ok(())
+        Ok(nwr)
     }
 
     fn is_offline(&self) -> bool { self.install_mode == &InstallMode::Offline }
 
+    /// Describe the channels we search for packages, e.g. "'stable' channel" or "'unstable',
+    /// 'stable' channels".
+    fn channels_description(&self) -> String {
+        let names = self.channels
+                        .iter()
+                        .map(|channel| format!("'{}'", channel))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+        if self.channels.len() == 1 {
+            format!("{} channel", names)
+        } else {
+            format!("{} channels", names)
+        }
+    }
+
     /// We may not want to use currently-installed packages if one
     /// can't be found in Builder in the given channel.
     ///
@@ -995,8 +1024,9 @@ impl<'a> InstallTask<'a> {
     {
         if let Ok(recommendations) = self.get_channel_recommendations((&ident, target), token) {
             if !recommendations.is_empty() {
-                ui.warn(format!("No releases of {} exist in the '{}' channel",
-                                &ident, self.channel))?;
+                ui.warn(format!("No releases of {} exist in the {}",
+                                &ident,
+                                self.channels_description()))?;
                 ui.warn("The following releases were found:")?;
                 for r in recommendations {
                     ui.warn(format!("  {} in the '{}' channel", r.1, r.0))?;
@@ -1036,3 +1066,220 @@ impl<'a> InstallTask<'a> {
         Ok(res)
     }
 }
+
+/// Return the first of `channels` (in order of precedence) for which `fetch` finds a package,
+/// along with what it found. Channels that don't have the package are skipped; any other error
+/// is returned immediately.
+fn first_channel_with<'c, T, F>(channels: &'c [ChannelIdent],
+                                mut fetch: F)
+                                -> Result<Option<(&'c ChannelIdent, T)>>
+    where F: FnMut(&ChannelIdent) -> Result<T>
+{
+    for channel in channels {
+        match fetch(channel) {
+            Ok(found) => return Ok(Some((channel, found))),
+            Err(Error::APIClient(APIError(StatusCode::NOT_FOUND, _))) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(None)
+}
+
+/// Call `fetch` until it succeeds, waiting out each of `delays` between attempts. A package
+/// that doesn't exist won't appear by asking again, so that error is returned immediately;
+/// any other error is retried, and once `delays` runs out the last one is returned wrapped in
+/// `DownloadRetriesExhausted`.
+fn fetch_with_retries<D, F>((ident, target): (&PackageIdent, PackageTarget),
+                            delays: D,
+                            mut fetch: F)
+                            -> Result<()>
+    where D: IntoIterator<Item = Duration>,
+          F: FnMut() -> Result<()>
+{
+    let mut attempts = 0;
+    let result = retry(delays, || {
+        attempts += 1;
+        match fetch() {
+            Ok(()) => OperationResult::Ok(()),
+            Err(e @ Error::APIClient(APIError(StatusCode::NOT_FOUND, _))) => {
+                OperationResult::Err(e)
+            }
+            Err(e) => OperationResult::Retry(e),
+        }
+    });
+    match result {
+        Ok(()) => Ok(()),
+        Err(retry::Error::Operation { error: e @ Error::APIClient(APIError(StatusCode::NOT_FOUND, _)),
+                                      .. }) => Err(e),
+        Err(retry::Error::Operation { error, .. }) => {
+            Err(Error::DownloadRetriesExhausted { ident: ident.clone(),
+                                                  target,
+                                                  attempts,
+                                                  last: Box::new(error) })
+        }
+        Err(e) => Err(Error::DownloadFailed(e.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::ui::UI;
+    use std::{io::Write,
+              sync::{Arc,
+                     Mutex}};
+    use tempfile::TempDir;
+    use termcolor::ColorChoice;
+
+    fn not_found() -> Error { Error::APIClient(APIError(StatusCode::NOT_FOUND, String::new())) }
+
+    /// Output written to a `UI` that the test can read back afterwards.
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.lock().unwrap().write(buf) }
+
+        fn flush(&mut self) -> io::Result<()> { Ok(()) }
+    }
+
+    fn core_fixtures_path() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..")
+                                                 .join("core")
+                                                 .join("tests")
+                                                 .join("fixtures")
+    }
+
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    #[test]
+    fn verified_artifacts_are_reported_with_their_signer() {
+        let fs_root = TempDir::new().expect("create temp dir");
+        let artifact_cache_path = fs_root.path().join("artifacts");
+        let key_cache_path = fs_root.path().join("keys");
+        fs::create_dir_all(&artifact_cache_path).unwrap();
+        fs::create_dir_all(&key_cache_path).unwrap();
+        let archive = "happyhumans-possums-8.1.4-20160427165340-x86_64-linux.hart";
+        fs::copy(core_fixtures_path().join(archive),
+                 artifact_cache_path.join(archive)).unwrap();
+        let key = "happyhumans-20160424223347.pub";
+        fs::copy(core_fixtures_path().join(key), key_cache_path.join(key)).unwrap();
+
+        let channels = [ChannelIdent::stable()];
+        let task = InstallTask { install_mode:        &InstallMode::Offline,
+                                 local_package_usage: &LocalPackageUsage::default(),
+                                 api_client:          Client::new("http://localhost",
+                                                                  "test",
+                                                                  "0.0.0",
+                                                                  Some(fs_root.path())).unwrap(),
+                                 channels:            &channels,
+                                 fs_root_path:        fs_root.path(),
+                                 artifact_cache_path: &artifact_cache_path,
+                                 key_cache_path:      &key_cache_path,
+                                 install_hook_mode:   InstallHookMode::default(), };
+        let out = SharedBuffer::default();
+        let mut ui = {
+            let out = out.clone();
+            UI::with_streams(Box::new(io::empty()),
+                             move || Box::new(out.clone()),
+                             || Box::new(io::sink()),
+                             ColorChoice::Never,
+                             false)
+        };
+        let ident = PackageIdent::from_str("happyhumans/possums/8.1.4/20160427165340").unwrap();
+        let ident = FullyQualifiedPackageIdent::from(&ident).unwrap();
+
+        task.get_cached_artifact(&mut ui, (&ident, PackageTarget::active_target()), None)
+            .expect("artifact verified");
+
+        let output = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert!(output.contains("happyhumans/possums/8.1.4/20160427165340 signed by \
+                                 happyhumans-20160424223347"),
+                "unexpected output: {}",
+                output);
+    }
+
+    #[test]
+    fn first_channel_with_prefers_the_highest_precedence_channel() {
+        let channels = vec![ChannelIdent::from("hotfix"),
+                            ChannelIdent::from("release"),
+                            ChannelIdent::stable()];
+        let found = first_channel_with(&channels, |channel| {
+                        match channel.as_str() {
+                            "release" => Ok("core/redis/4.0.14/20190319155852"),
+                            "stable" => Ok("core/redis/3.2.4/20170514150022"),
+                            _ => Err(not_found()),
+                        }
+                    }).expect("no error");
+
+        assert_eq!(found,
+                   Some((&channels[1], "core/redis/4.0.14/20190319155852")));
+    }
+
+    #[test]
+    fn first_channel_with_returns_none_when_no_channel_has_the_package() {
+        let channels = vec![ChannelIdent::unstable(), ChannelIdent::stable()];
+        let found = first_channel_with(&channels, |_| Err::<(), _>(not_found())).expect("no error");
+
+        assert_eq!(found, None);
+    }
+
+    #[test]
+    fn first_channel_with_stops_at_other_errors() {
+        let channels = vec![ChannelIdent::unstable(), ChannelIdent::stable()];
+        let mut searched = Vec::new();
+        let result = first_channel_with(&channels, |channel| {
+            searched.push(channel.clone());
+            Err::<(), _>(Error::PackageNotFound(channel.to_string()))
+        });
+
+        assert!(result.is_err());
+        assert_eq!(searched, vec![ChannelIdent::unstable()]);
+    }
+
+    #[test]
+    fn fetch_with_retries_reports_the_attempts_once_retries_are_exhausted() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let mut calls = 0;
+        let result = fetch_with_retries((&ident, PackageTarget::active_target()),
+                                        delay::NoDelay.take(RETRIES),
+                                        || {
+                                            calls += 1;
+                                            Err(Error::APIClient(APIError(StatusCode::BAD_GATEWAY,
+                                                                          String::new())))
+                                        });
+
+        match result {
+            Err(Error::DownloadRetriesExhausted { ident: failed,
+                                                  attempts,
+                                                  last,
+                                                  .. }) => {
+                assert_eq!(failed, ident);
+                assert_eq!(attempts, RETRIES + 1);
+                match *last {
+                    Error::APIClient(APIError(StatusCode::BAD_GATEWAY, _)) => {}
+                    other => panic!("Expected the last transient error, got {:?}", other),
+                }
+            }
+            other => panic!("Expected DownloadRetriesExhausted, got {:?}", other),
+        }
+        assert_eq!(calls, RETRIES + 1);
+    }
+
+    #[test]
+    fn fetch_with_retries_does_not_retry_a_missing_package() {
+        let ident = PackageIdent::from_str("core/redis/4.0.14/20190319155852").unwrap();
+        let mut calls = 0;
+        let result = fetch_with_retries((&ident, PackageTarget::active_target()),
+                                        delay::NoDelay.take(RETRIES),
+                                        || {
+                                            calls += 1;
+                                            Err(not_found())
+                                        });
+
+        match result {
+            Err(Error::APIClient(APIError(StatusCode::NOT_FOUND, _))) => {}
+            other => panic!("Expected the not found error, got {:?}", other),
+        }
+        assert_eq!(calls, 1);
+    }
+}
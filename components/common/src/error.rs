@@ -11,7 +11,8 @@ use toml;
 
 use crate::{api_client,
             hcore::{self,
-                    package::PackageIdent}};
+                    package::{PackageIdent,
+                              PackageTarget}}};
 
 pub type Result<T> = result::Result<T, Error>;
 
@@ -26,6 +27,15 @@ pub enum Error {
     ChannelNotFound,
     CryptoKeyError(String),
     DownloadFailed(String),
+    /// Occurs when every attempt to download a package failed with an error that might have
+    /// gone away by trying again, such as the depot being unreachable. `last` is the error from
+    /// the final attempt.
+    DownloadRetriesExhausted {
+        ident:    PackageIdent,
+        target:   PackageTarget,
+        attempts: usize,
+        last:     Box<Error>,
+    },
     EditorEnv(env::VarError),
     EditStatus,
     FileNameError,
@@ -91,6 +101,13 @@ impl fmt::Display for Error {
             Error::ChannelNotFound => "Channel not found".to_string(),
             Error::CryptoKeyError(ref s) => format!("Missing or invalid key: {}", s),
             Error::DownloadFailed(ref msg) => msg.to_string(),
+            Error::DownloadRetriesExhausted { ref ident,
+                                              ref target,
+                                              attempts,
+                                              ref last, } => {
+                format!("We tried {} times but could not download {} for {}. Last error was: {}",
+                        attempts, ident, target, last)
+            }
             Error::EditorEnv(ref e) => format!("Missing EDITOR environment variable: {}", e),
             Error::EditStatus => "Failed edit text command".to_string(),
             Error::FileNameError => "Failed to extract a filename".to_string(),
@@ -152,7 +169,14 @@ impl fmt::Display for Error {
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::DownloadRetriesExhausted { ref last, .. } => Some(last.as_ref()),
+            _ => None,
+        }
+    }
+}
 
 impl From<api_client::Error> for Error {
     fn from(err: api_client::Error) -> Self { Error::APIClient(err) }
@@ -206,6 +206,44 @@ macro_rules! env_config_int {
     };
 }
 
+/// Declare a struct `$wrapping_type` that stores a `bool` and
+/// implements the `Config` trait so that its value can be overridden by `$env_var`.
+/// The variable is parsed with `parse_bool`, so `HAB_FOO=false` turns a setting off
+/// rather than on.
+///
+/// This is a thin wrapper around `env_config`. See its documentation for more details.
+///
+/// Example usage:
+/// ```
+/// habitat_core::env_config_bool!(#[derive(Debug)]
+///                                DatFileWal,
+///                                HAB_DAT_FILE_WAL,
+///                                false);
+/// ```
+#[macro_export]
+macro_rules! env_config_bool {
+    ($(#[$attr:meta])* $vis:vis $wrapping_type:ident, $env_var:ident, $default_value:expr) => {
+        $crate::env_config!($(#[$attr])*
+                            $vis $wrapping_type,
+                            bool,
+                            $env_var,
+                            $default_value,
+                            String,
+                            s,
+                            $crate::env::parse_bool(s).map(Self).ok_or_else(|| s.to_string()));
+    };
+}
+
+/// Parse an environment variable's value as a boolean. Besides `true` and `false`, this accepts
+/// `1` and `0`, `yes` and `no`, and `on` and `off`, in any case.
+pub fn parse_bool(s: &str) -> Option<bool> {
+    match s.trim().to_lowercase().as_str() {
+        "true" | "1" | "yes" | "on" => Some(true),
+        "false" | "0" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
 /// Declare a struct `$wrapping_type` that stores a `String` and
 /// implements the `Config` trait so that its value can be overridden by `$env_var`.
 ///
@@ -392,3 +430,21 @@ pub trait Config: Default + FromStr {
               env_value.as_ref());
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::parse_bool;
+
+    #[test]
+    fn parse_bool_reads_false_as_false() {
+        for value in &["false", "FALSE", "0", "no", "off", " Off "] {
+            assert_eq!(parse_bool(value), Some(false), "{:?}", value);
+        }
+        for value in &["true", "True", "1", "yes", "on"] {
+            assert_eq!(parse_bool(value), Some(true), "{:?}", value);
+        }
+        for value in &["", "2", "enabled"] {
+            assert_eq!(parse_bool(value), None, "{:?}", value);
+        }
+    }
+}
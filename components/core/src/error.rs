@@ -277,9 +277,13 @@ impl fmt::Display for Error {
                         e)
             }
             Error::InvalidPackageTarget(ref e) => {
+                let supported: Vec<&str> = package::PackageTarget::all_known().iter()
+                                                                              .map(|t| t.as_ref())
+                                                                              .collect();
                 format!("Invalid package target: {}. A valid target is in the form \
-                         architecture-platform (example: x86_64-linux)",
-                        e)
+                         architecture-platform (example: x86_64-linux). Supported targets: {}",
+                        e,
+                        supported.join(", "))
             }
             Error::InvalidPackageType(ref e) => format!("Invalid package type: {}.", e),
             Error::InvalidServiceGroup(ref e) => {
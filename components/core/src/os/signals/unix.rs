@@ -81,6 +81,7 @@ fn set_signal_handlers() {
 fn from_signal_code(code: SignalCode) -> Option<Signal> {
     match code {
         libc::SIGHUP => Some(Signal::HUP),
+        libc::SIGUSR1 => Some(Signal::USR1),
         libc::SIGCHLD => Some(Signal::CHLD),
         _ => None,
     }
@@ -2,6 +2,8 @@ use crate::{error::{Error,
                     Result},
             package::PackageTarget};
 use regex::Regex;
+use semver::{Version,
+             VersionReq};
 use serde_derive::{Deserialize,
                    Serialize};
 use std::{borrow::Cow,
@@ -133,13 +135,17 @@ impl PackageIdent {
         if self.name != other.name {
             return self.name.cmp(&other.name);
         }
-        match version_sort(self.version.as_ref().unwrap(),
-                           other.version.as_ref().unwrap())
-        {
-            Ok(Ordering::Equal) => self.release.cmp(&other.release),
-            Ok(ordering) => ordering,
-            Err(_) => Ordering::Less,
-        }
+        version_and_release_cmp(self, other)
+    }
+
+    /// Whether this package's version falls within a semantic version range, such as
+    /// `>=3.0, <4.0`. Unlike `satisfies`, this never matches a package without a version or
+    /// whose version isn't valid semver.
+    pub fn satisfies_semver(&self, range: &VersionReq) -> bool {
+        self.version
+            .as_ref()
+            .and_then(|version| Version::parse(version).ok())
+            .map_or(false, |version| range.matches(&version))
     }
 
     fn archive_name_impl(&self, target: PackageTarget) -> Result<String> {
@@ -241,29 +247,7 @@ impl PartialOrd for PackageIdent {
         if self.release.is_some() && other.release.is_none() {
             return Some(Ordering::Greater);
         }
-        match version_sort(self.version.as_ref().unwrap(),
-                           other.version.as_ref().unwrap())
-        {
-            ord @ Ok(Ordering::Greater) | ord @ Ok(Ordering::Less) => ord.ok(),
-            Ok(Ordering::Equal) => Some(self.release.cmp(&other.release)),
-            Err(_) => {
-                // TODO: Can we do better than this? As long as we allow
-                // non-numeric versions to co-exist with numeric ones, we
-                // always have potential for incorrect ordering no matter
-                // what we choose - eg, "master" vs. "0.x.x" (real examples)
-                debug!("Comparing non-numeric versions: {} {}",
-                       self.version.as_ref().unwrap(),
-                       other.version.as_ref().unwrap());
-                match self.version
-                          .as_ref()
-                          .unwrap()
-                          .cmp(other.version.as_ref().unwrap())
-                {
-                    ord @ Ordering::Greater | ord @ Ordering::Less => Some(ord),
-                    Ordering::Equal => Some(self.release.cmp(&other.release)),
-                }
-            }
-        }
+        Some(version_and_release_cmp(self, other))
     }
 }
 
@@ -274,16 +258,76 @@ impl Ord for PackageIdent {
     /// * If the names are not equal, they cannot be compared.
     /// * If the versions are greater/lesser, return that as the ordering.
     /// * If the versions are equal, return the greater/lesser for the release.
+    ///
+    /// Versions are compared segment by segment, see `version_cmp`.
     fn cmp(&self, other: &PackageIdent) -> Ordering {
         if self.name != other.name {
             return self.name.cmp(&other.name);
         }
-        match version_sort(self.version.as_ref().unwrap(),
-                           other.version.as_ref().unwrap())
-        {
-            Ok(Ordering::Equal) => self.release.cmp(&other.release),
-            Ok(ordering) => ordering,
-            Err(_) => Ordering::Less,
+        version_and_release_cmp(self, other)
+    }
+}
+
+/// Compare the versions, and then the releases, of two fully qualified packages.
+fn version_and_release_cmp(a: &PackageIdent, b: &PackageIdent) -> Ordering {
+    let a_version = a.version.as_ref().unwrap();
+    let b_version = b.version.as_ref().unwrap();
+    version_cmp(a_version, b_version).then_with(|| a.release.cmp(&b.release))
+}
+
+/// Compare two versions in a single total order, numeric or not.
+///
+/// Versions are split into segments on `.` and `-` and compared segment by segment. Numeric
+/// segments compare by value and always rank below non-numeric ones, which compare as strings.
+/// A `-` starts a pre-release extension, so `1.0.0-alpha1` sorts before `1.0.0`, and a version
+/// that runs out of segments sorts before one that goes on, so `1.2` sorts before `1.2.1`.
+/// Versions whose segments all compare equal, such as `1.01` and `1.1`, fall back to their
+/// plain strings, so only identical versions are equal.
+fn version_cmp(a: &str, b: &str) -> Ordering {
+    let a_segments = version_segments(a);
+    let b_segments = version_segments(b);
+    let len = a_segments.len().max(b_segments.len());
+    (0..len).map(|i| {
+                let a_segment = a_segments.get(i).unwrap_or(&VersionSegment::End);
+                let b_segment = b_segments.get(i).unwrap_or(&VersionSegment::End);
+                a_segment.cmp(b_segment)
+            })
+            .find(|ordering| *ordering != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.cmp(b))
+}
+
+fn version_segments(version: &str) -> Vec<VersionSegment<'_>> {
+    let mut segments = Vec::new();
+    for (i, part) in version.split('-').enumerate() {
+        if i > 0 {
+            segments.push(VersionSegment::Extension);
+        }
+        segments.extend(part.split('.').map(VersionSegment::from));
+    }
+    segments
+}
+
+/// One segment of a version, as compared by `version_cmp`. The variants rank in the order they
+/// are declared.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionSegment<'a> {
+    /// A `-`, which starts a pre-release extension.
+    Extension,
+    /// The end of the shorter of two versions.
+    End,
+    /// All digits, held as the length and digits without leading zeros so it compares by value.
+    Numeric(usize, &'a str),
+    Text(&'a str),
+}
+
+impl<'a> From<&'a str> for VersionSegment<'a> {
+    fn from(segment: &'a str) -> Self {
+        if !segment.is_empty() && segment.bytes().all(|b| b.is_ascii_digit()) {
+            let digits = segment.trim_start_matches('0');
+            VersionSegment::Numeric(digits.len(), digits)
+        } else {
+            VersionSegment::Text(segment)
         }
     }
 }
@@ -504,6 +548,145 @@ mod tests {
         }
     }
 
+    #[test]
+    fn package_ident_ord_compares_versions_numerically() {
+        let ident = |version: &str| {
+            PackageIdent::new("core", "redis", Some(version), Some("20150521131555"))
+        };
+        assert_eq!(ident("9.0.0").cmp(&ident("10.0.0")), Ordering::Less);
+        assert_eq!(ident("10.0.0").cmp(&ident("9.0.0")), Ordering::Greater);
+        assert_eq!(ident("1.2").cmp(&ident("1.2.1")), Ordering::Less);
+        assert_eq!(ident("1.2.1").cmp(&ident("1.2.1")), Ordering::Equal);
+    }
+
+    #[test]
+    fn package_ident_ord_non_numeric_version_is_antisymmetric() {
+        let numeric = PackageIdent::new("core", "redis", Some("0.1.0"), Some("20150521131555"));
+        let non_numeric =
+            PackageIdent::new("core", "redis", Some("master"), Some("20150521131555"));
+        assert_eq!(numeric.cmp(&non_numeric),
+                   non_numeric.cmp(&numeric).reverse());
+        assert_ne!(numeric.cmp(&non_numeric), Ordering::Equal);
+    }
+
+    #[test]
+    fn package_ident_ord_ranks_numeric_segments_below_non_numeric_ones() {
+        let ident = |version: &str| {
+            PackageIdent::new("core", "redis", Some(version), Some("20150521131555"))
+        };
+        assert!(ident("1.9") < ident("1.10"));
+        assert!(ident("1.10") < ident("1.10a"));
+        assert!(ident("1.9") < ident("1.10a"));
+        assert!(ident("0.1.0") < ident("master"));
+        assert!(ident("1.0.0-alpha1") < ident("1.0.0"));
+        assert!(ident("1.0.0-alpha1") < ident("1.0.0-alpha2"));
+        assert!(ident("2016i") < ident("2016j"));
+        assert_ne!(ident("1.01").cmp(&ident("1.1")), Ordering::Equal);
+    }
+
+    /// Versions picked to mix numeric, non-numeric, pre-release and malformed segments.
+    const ORD_SAMPLE_VERSIONS: &[&str] = &["1.9",
+                                           "1.10",
+                                           "1.10a",
+                                           "1.9a",
+                                           "1.0",
+                                           "1.0.0",
+                                           "1.00",
+                                           "01",
+                                           "1",
+                                           "1.0.0-alpha1",
+                                           "1.0.0-alpha2",
+                                           "1.0.0-beta",
+                                           "1.0.0-1",
+                                           "1.0.0alpha",
+                                           "1-",
+                                           "1..2",
+                                           ".1",
+                                           "",
+                                           "2016i",
+                                           "2016",
+                                           "master",
+                                           "0.x.x",
+                                           "x",
+                                           "10.0.0",
+                                           "9.0.0",
+                                           "20150521131347",
+                                           "1.2.3-rc.1",
+                                           "1.2.3-rc.10",
+                                           "1.2.3-rc-1"];
+
+    fn sample_idents() -> Vec<PackageIdent> {
+        ORD_SAMPLE_VERSIONS.iter()
+                           .flat_map(|version| {
+                               vec![PackageIdent::new("core",
+                                                      "redis",
+                                                      Some(*version),
+                                                      Some("20150521131555")),
+                                    PackageIdent::new("core",
+                                                      "redis",
+                                                      Some(*version),
+                                                      Some("20150521131556")),]
+                           })
+                           .collect()
+    }
+
+    #[test]
+    fn package_ident_ord_is_antisymmetric() {
+        let idents = sample_idents();
+        for a in &idents {
+            for b in &idents {
+                assert_eq!(a.cmp(b), b.cmp(a).reverse(), "{} vs {}", a, b);
+                assert_eq!(a.cmp(b) == Ordering::Equal, a == b, "{} vs {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn package_ident_ord_is_transitive() {
+        let idents = sample_idents();
+        for a in &idents {
+            for b in idents.iter().filter(|b| a.cmp(b) != Ordering::Greater) {
+                for c in idents.iter().filter(|c| b.cmp(c) != Ordering::Greater) {
+                    assert_ne!(a.cmp(c), Ordering::Greater, "{} <= {} <= {}", a, b, c);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn satisfies_semver_range() {
+        let range = VersionReq::parse(">=3.0, <4.0").unwrap();
+        let ident = |version: &str| PackageIdent::new("core", "redis", Some(version), None);
+        assert!(ident("3.0.0").satisfies_semver(&range));
+        assert!(ident("3.2.4").satisfies_semver(&range));
+        assert!(!ident("4.0.0").satisfies_semver(&range));
+        assert!(!ident("2.9.9").satisfies_semver(&range));
+    }
+
+    #[test]
+    fn satisfies_semver_pre_release() {
+        let ident = PackageIdent::new("core", "redis", Some("3.5.0-beta.1"), None);
+        // Pre-releases only match ranges that mention a pre-release of the same version
+        assert!(!ident.satisfies_semver(&VersionReq::parse(">=3.0, <4.0").unwrap()));
+        assert!(ident.satisfies_semver(&VersionReq::parse(">=3.5.0-beta.0").unwrap()));
+        assert!(!ident.satisfies_semver(&VersionReq::parse(">=3.5.0").unwrap()));
+    }
+
+    #[test]
+    fn satisfies_semver_ignores_build_metadata() {
+        let ident = PackageIdent::new("core", "redis", Some("3.2.4+build.5"), None);
+        assert!(ident.satisfies_semver(&VersionReq::parse(">=3.0, <4.0").unwrap()));
+        assert!(ident.satisfies_semver(&VersionReq::parse("=3.2.4").unwrap()));
+    }
+
+    #[test]
+    fn satisfies_semver_is_false_for_invalid_semver() {
+        let range = VersionReq::parse("*").unwrap();
+        assert!(!PackageIdent::new("core", "redis", Some("3.2"), None).satisfies_semver(&range));
+        assert!(!PackageIdent::new("core", "redis", Some("master"), None).satisfies_semver(&range));
+        assert!(!PackageIdent::new("core", "redis", None, None).satisfies_semver(&range));
+    }
+
     #[test]
     fn package_ident_partial_ord_bad_name() {
         let a = PackageIdent::new("awesome".to_string(),
@@ -63,7 +63,8 @@
 //! [musl]: https://www.musl-libc.org/
 //! [rust_triple]: https://github.com/rust-lang/rust/tree/master/src/librustc_back/target
 
-use std::{fmt,
+use std::{cmp::Ordering,
+          fmt,
           ops::Deref,
           result,
           str::FromStr};
@@ -413,6 +414,18 @@ impl PackageTarget {
     /// println!("All supported targets: [{}]", targets.join(", "));
     /// ```
     pub fn targets() -> ::std::slice::Iter<'static, PackageTarget> { PACKAGE_TARGETS.iter() }
+
+    /// Returns all supported `PackageTarget`s as a slice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use habitat_core::package::PackageTarget;
+    ///
+    /// let active = PackageTarget::active_target();
+    /// assert!(PackageTarget::all_known().contains(&active));
+    /// ```
+    pub fn all_known() -> &'static [PackageTarget] { PACKAGE_TARGETS }
 }
 
 impl fmt::Display for PackageTarget {
@@ -437,6 +450,16 @@ impl AsRef<str> for PackageTarget {
     fn as_ref(&self) -> &str { self.0.as_str() }
 }
 
+/// Targets are ordered by their canonical `architecture-platform` name, so the order doesn't
+/// depend on which targets are compiled in or the order they are declared in.
+impl Ord for PackageTarget {
+    fn cmp(&self, other: &PackageTarget) -> Ordering { self.0.as_str().cmp(other.0.as_str()) }
+}
+
+impl PartialOrd for PackageTarget {
+    fn partial_cmp(&self, other: &PackageTarget) -> Option<Ordering> { Some(self.cmp(other)) }
+}
+
 impl<'d> serde::Deserialize<'d> for PackageTarget {
     fn deserialize<D>(deserializer: D) -> result::Result<Self, D::Error>
         where D: serde::Deserializer<'d>
@@ -569,6 +592,15 @@ mod test {
         assert_eq!("x86_64-linux", target.as_ref());
     }
 
+    #[test]
+    #[cfg(all(feature = "x86_64-linux", feature = "x86_64-darwin"))]
+    fn package_target_ord_is_by_canonical_name() {
+        let darwin = PackageTarget(Type::X86_64_Darwin);
+        let linux = PackageTarget(Type::X86_64_Linux);
+        assert!(darwin < linux);
+        assert_eq!(darwin.cmp(&linux), darwin.as_ref().cmp(linux.as_ref()));
+    }
+
     #[test]
     #[cfg(feature = "x86_64-linux")]
     fn serialize() {
@@ -621,6 +653,28 @@ mod test {
         assert_eq!(None, Type::X86_64_Windows.variant());
     }
 
+    #[test]
+    fn all_known_includes_linux_and_darwin() {
+        let known: Vec<&str> = PackageTarget::all_known().iter()
+                                                         .map(|t| t.as_ref())
+                                                         .collect();
+
+        assert!(!known.is_empty());
+        #[cfg(feature = "x86_64-linux")]
+        assert!(known.contains(&"x86_64-linux"));
+        #[cfg(feature = "x86_64-darwin")]
+        assert!(known.contains(&"x86_64-darwin"));
+    }
+
+    #[test]
+    fn invalid_target_error_lists_supported_targets() {
+        let err = PackageTarget::from_str("sparc-solaris").unwrap_err();
+
+        for target in PackageTarget::all_known() {
+            assert!(err.to_string().contains(target.as_ref()));
+        }
+    }
+
     #[test]
     #[cfg(feature = "x86_64-windows")]
     fn package_target_iter_no_variant() {
@@ -2,7 +2,8 @@ use crate::command::studio;
 use clap::{App,
            AppSettings,
            Arg,
-           ArgMatches};
+           ArgMatches,
+           SubCommand};
 use habitat_common::{cli::{BINLINK_DIR_ENVVAR,
                            DEFAULT_BINLINK_DIR,
                            PACKAGE_TARGET_ENVVAR,
@@ -824,6 +825,7 @@ pub fn sup_commands(feature_flags: FeatureFlag) -> App<'static, 'static> {
     (@setting VersionlessSubcommands)
     (@setting SubcommandRequiredElseHelp)
     (subcommand: sub_sup_bash().aliases(&["b", "ba", "bas"]))
+    (subcommand: sub_sup_dat_file())
     (subcommand: sub_sup_depart().aliases(&["d", "de", "dep", "depa", "depart"]))
     (subcommand: sub_sup_run(feature_flags).aliases(&["r", "ru"]))
     (subcommand: sub_sup_secret().aliases(&["sec", "secr"]))
@@ -866,6 +868,33 @@ fn arg_cache_key_path(help_text: &'static str) -> Arg<'static, 'static> {
                                     .help(&help_text)
 }
 
+fn arg_dat_file_cache_key_path() -> Arg<'static, 'static> {
+    arg_cache_key_path("Path to search for the ring key of a sealed dat file. Default value is \
+                        hab/cache/keys if root and .hab/cache/keys under the home directory \
+                        otherwise.")
+}
+
+fn arg_dat_file_ring() -> Arg<'static, 'static> {
+    Arg::with_name("RING").long("ring")
+                          .short("r")
+                          .takes_value(true)
+                          .env(RING_ENVVAR)
+                          .conflicts_with("RING_KEY")
+                          .validator(non_empty)
+                          .help("The name of the ring whose key the dat file was sealed with")
+}
+
+fn arg_dat_file_ring_key() -> Arg<'static, 'static> {
+    Arg::with_name("RING_KEY").long("ring-key")
+                              .takes_value(true)
+                              .env(RING_KEY_ENVVAR)
+                              .conflicts_with("RING")
+                              .validator(non_empty)
+                              .hidden(true)
+                              .help("The contents of the ring key the dat file was sealed with. \
+                                     Use the corresponding environment variable instead.")
+}
+
 fn arg_target() -> Arg<'static, 'static> {
     Arg::with_name("PKG_TARGET").takes_value(true)
                                 .validator(valid_target)
@@ -912,8 +941,10 @@ fn sub_pkg_install(feature_flags: FeatureFlag) -> App<'static, 'static> {
             "Specify an alternate Builder endpoint. If not specified, the value will \
                          be taken from the HAB_BLDR_URL environment variable if defined. (default: \
                          https://bldr.habitat.sh)")
-        (@arg CHANNEL: --channel -c +takes_value default_value[stable] env(ChannelIdent::ENVVAR)
-            "Install from the specified release channel")
+        (@arg CHANNEL: --channel -c +takes_value +multiple number_of_values(1)
+            default_value[stable] env(ChannelIdent::ENVVAR)
+            "Install from the specified release channel. Repeat to search several channels in \
+            order of precedence, installing from the first that has the package")
         (@arg PKG_IDENT_OR_ARTIFACT: +required +multiple
             "One or more Habitat package identifiers (ex: acme/redis) and/or filepaths \
             to a Habitat Artifact (ex: /home/acme-redis-3.0.7-21120102031201-x86_64-linux.hart)")
@@ -970,6 +1001,49 @@ pub fn sub_sup_depart() -> App<'static, 'static> {
     )
 }
 
+pub fn sub_sup_dat_file() -> App<'static, 'static> {
+    // `clap_app!` can't name a subcommand with a hyphen in it
+    SubCommand::with_name("dat-file")
+        .about("Commands relating to a Habitat Supervisor's gossip dat file")
+        .setting(AppSettings::ArgRequiredElseHelp)
+        .subcommand(clap_app!(@subcommand inspect =>
+            (about: "Print the header of a dat file and every rumor in it")
+            // set custom usage string, otherwise the binary
+            // is displayed confusingly as `hab-sup`
+            // see: https://github.com/kbknapp/clap-rs/blob/2724ec5399c500b12a1a24d356f4090f4816f5e2/src/app/mod.rs#L373-L394
+            (usage: "hab sup dat-file inspect [FLAGS] <DAT_FILE>")
+            (@arg DAT_FILE: +required +takes_value {file_exists}
+                "Path to the dat file, such as /hab/sup/default/data/<MEMBER_ID>.rst")
+            (@arg JSON: -j --json "Print the contents as JSON")
+            (arg: arg_dat_file_cache_key_path())
+            (arg: arg_dat_file_ring())
+            (arg: arg_dat_file_ring_key())
+        ))
+        .subcommand(clap_app!(@subcommand export =>
+            (about: "Write the members and rumors in a dat file to a bundle that another host can \
+                     import. The Supervisor that wrote the dat file is left out of the bundle")
+            (usage: "hab sup dat-file export [OPTIONS] <DAT_FILE> <BUNDLE>")
+            (@arg DAT_FILE: +required +takes_value {file_exists}
+                "Path to the dat file, such as /hab/sup/default/data/<MEMBER_ID>.rst")
+            (@arg BUNDLE: +required +takes_value "Path to write the bundle to")
+            (@arg FORMAT: --format +takes_value possible_value[json protobuf] default_value("json")
+                "The format to write the bundle in")
+            (arg: arg_dat_file_cache_key_path())
+            (arg: arg_dat_file_ring())
+            (arg: arg_dat_file_ring_key())
+        ))
+        .subcommand(clap_app!(@subcommand import =>
+            (about: "Write a dat file from a bundle written by `hab sup dat-file export`, \
+                     replacing any dat file already at that path")
+            (usage: "hab sup dat-file import [OPTIONS] <BUNDLE> <DAT_FILE>")
+            (@arg BUNDLE: +required +takes_value {file_exists} "Path to the bundle")
+            (@arg DAT_FILE: +required +takes_value
+                "Path to write the dat file to, such as /hab/sup/default/data/<MEMBER_ID>.rst")
+            (@arg FORMAT: --format +takes_value possible_value[json protobuf] default_value("json")
+                "The format the bundle was written in")
+        ))
+}
+
 pub fn sub_sup_secret() -> App<'static, 'static> {
     clap_app!(@subcommand secret =>
         (about: "Commands relating to a Habitat Supervisor's Control Gateway secret")
@@ -1076,6 +1150,32 @@ pub fn sub_sup_run(feature_flags: FeatureFlag) -> App<'static, 'static> {
                                                             Implies NO_COLOR")
                             (@arg HEALTH_CHECK_INTERVAL: --("health-check-interval") -i +takes_value {valid_health_check_interval}
                              "The interval (seconds) on which to run health checks [default: 30]")
+                            (@arg PERSIST_INTERVAL: --("persist-interval") +takes_value {valid_positive_numeric::<u64>}
+                             "The interval (seconds) on which to persist gossip rumors to disk. If not specified, \
+                              the value will be taken from the HAB_PERSIST_LOOP_PERIOD_SECS environment variable \
+                              if defined. [default: 30]")
+                            (@arg PERSIST_AFTER_CHANGES: --("persist-after-changes") +takes_value {valid_numeric::<usize>}
+                             "Persist gossip rumors as soon as this many have changed, rather than waiting out \
+                              the persist interval. If not specified, the value will be taken from the \
+                              HAB_PERSIST_AFTER_CHANGES environment variable if defined. [default: 0, which \
+                              only persists on the interval]")
+                            (@arg NO_PERSIST_ON_SHUTDOWN: --("no-persist-on-shutdown")
+                             "Don't persist gossip rumors when the Supervisor shuts down, such as on SIGTERM. \
+                              Changes since the last persist are then lost. [default: false]")
+                            (@arg PROBE_INTERVAL: --("probe-interval") +takes_value {valid_positive_numeric::<i64>}
+                             "The interval (milliseconds) between probes of other Supervisors' liveness. Raise it \
+                              on congested networks to avoid false suspicion, or lower it to detect failures sooner. \
+                              Suspicion timeouts are counted in probe intervals. [default: 3100]")
+                            (@arg ZONE: --zone +takes_value env("HAB_SUP_ZONE")
+                             "The zone, such as a datacenter or availability zone, this Supervisor runs in. \
+                              Gossip reaches Supervisors in the same zone first, while still sending to other \
+                              zones every round.")
+                            (@arg TIMEOUT_OVERRIDE: --("timeout-override") +takes_value +multiple number_of_values(1)
+                             "Scale the suspicion and departure timeouts of some Supervisors, written as \
+                              <selector>,<suspicion multiplier>,<departure multiplier>. The selector is either \
+                              label:<label>=<value> or address:<prefix> (ex: label:zone=eu-west,3,2). \
+                              Multipliers must be greater than 0. Repeat to add more overrides; the first one \
+                              that matches a Supervisor applies to it.")
     );
 
     let sub = if feature_flags.contains(FeatureFlag::EVENT_STREAM) {
@@ -1352,6 +1452,17 @@ fn valid_numeric<T: FromStr>(val: String) -> result::Result<(), String> {
     }
 }
 
+#[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
+fn valid_positive_numeric<T>(val: String) -> result::Result<(), String>
+    where T: FromStr + PartialOrd + Default
+{
+    match val.parse::<T>() {
+        Ok(ref n) if *n > T::default() => Ok(()),
+        Ok(_) => Err(format!("'{}' must be greater than 0", &val)),
+        Err(_) => Err(format!("'{}' is not a valid number", &val)),
+    }
+}
+
 #[allow(clippy::needless_pass_by_value)] // Signature required by CLAP
 fn valid_health_check_interval(val: String) -> result::Result<(), String> {
     match HealthCheckInterval::from_str(&val) {
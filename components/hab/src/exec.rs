@@ -72,7 +72,7 @@ pub fn command_from_min_pkg(ui: &mut UI,
             retry(delay::NoDelay.take(RETRY_LIMIT), || {
                 common::command::package::install::start(ui,
                                                          &default_bldr_url(),
-                                                         &internal_tooling_channel(),
+                                                         &[internal_tooling_channel()],
                                                          &(ident.clone(),
                                                            PackageTarget::active_target())
                                                                                           .into(),
@@ -742,7 +742,7 @@ fn sub_plan_render(ui: &mut UI, m: &ArgMatches<'_>) -> Result<()> {
 
 fn sub_pkg_install(ui: &mut UI, m: &ArgMatches<'_>, feature_flags: FeatureFlag) -> Result<()> {
     let url = bldr_url_from_matches(&m)?;
-    let channel = channel_from_matches_or_default(m);
+    let channels = channels_from_matches_or_default(m);
     let install_sources = install_sources_from_matches(m)?;
     let token = maybe_auth_token(&m);
     let install_mode =
@@ -771,7 +771,7 @@ fn sub_pkg_install(ui: &mut UI, m: &ArgMatches<'_>, feature_flags: FeatureFlag)
         let pkg_install =
             common::command::package::install::start(ui,
                                                      &url,
-                                                     &channel,
+                                                     &channels,
                                                      install_source,
                                                      PRODUCT,
                                                      VERSION,
@@ -1377,6 +1377,7 @@ fn exec_subcommand_if_called(ui: &mut UI) -> Result<()> {
         ("sup", "", "")
         | ("sup", "term", _)
         | ("sup", "bash", _)
+        | ("sup", "dat-file", _)
         | ("sup", "sh", _)
         | ("sup", "-V", _)
         | ("sup", "--version", _) => command::sup::start(ui, &args_after_first(2)),
@@ -1534,6 +1535,14 @@ fn channel_from_matches_or_default(matches: &ArgMatches<'_>) -> ChannelIdent {
     channel_from_matches(matches).unwrap_or_else(ChannelIdent::configured_value)
 }
 
+/// Resolve the channels to search, in order of precedence, for commands that accept more than
+/// one. Taken from the environment or from CLI args.
+fn channels_from_matches_or_default(matches: &ArgMatches<'_>) -> Vec<ChannelIdent> {
+    matches.values_of("CHANNEL")
+           .map(|channels| channels.map(ChannelIdent::from).collect())
+           .unwrap_or_else(|| vec![ChannelIdent::configured_value()])
+}
+
 /// Resolve a target. Default to x86_64-linux if none specified
 fn target_from_matches(matches: &ArgMatches<'_>) -> Result<PackageTarget> {
     matches.value_of("PKG_TARGET")
@@ -2014,6 +2023,70 @@ mod test {
         }
     }
 
+    mod channels_from_matches {
+        use super::*;
+
+        habitat_common::locked_env_var!(HAB_BLDR_CHANNEL, lock_channel_env_var);
+
+        fn matches_for_pkg_install<'a>(pkg_install_args: &'a [&'a str]) -> ArgMatches<'a> {
+            let args = ["hab", "pkg", "install"].iter()
+                                                .chain(pkg_install_args.iter());
+            let app_matches = cli::get(FeatureFlag::empty()).get_matches_from_safe(args)
+                                                            .unwrap();
+            match app_matches.subcommand() {
+                ("pkg", Some(matches)) => {
+                    match matches.subcommand() {
+                        ("install", Some(m)) => m.clone(),
+                        _ => unreachable!(),
+                    }
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        #[test]
+        fn default_channel_is_stable() {
+            let env_var = lock_channel_env_var();
+            env_var.unset();
+
+            let matches = matches_for_pkg_install(&["origin/pkg"]);
+            assert_eq!(channels_from_matches_or_default(&matches),
+                       vec![ChannelIdent::stable()]);
+        }
+
+        #[test]
+        fn repeated_channels_are_kept_in_order() {
+            let env_var = lock_channel_env_var();
+            env_var.unset();
+
+            let matches = matches_for_pkg_install(&["--channel",
+                                                    "hotfix",
+                                                    "-c",
+                                                    "release",
+                                                    "--channel",
+                                                    "stable",
+                                                    "origin/pkg"]);
+            assert_eq!(channels_from_matches_or_default(&matches),
+                       vec![ChannelIdent::from("hotfix"),
+                            ChannelIdent::from("release"),
+                            ChannelIdent::stable()]);
+        }
+
+        #[test]
+        fn each_channel_takes_a_single_value() {
+            let env_var = lock_channel_env_var();
+            env_var.unset();
+
+            let matches = matches_for_pkg_install(&["-c", "hotfix", "origin/pkg", "origin/other"]);
+            let idents: Vec<&str> = matches.values_of("PKG_IDENT_OR_ARTIFACT")
+                                           .unwrap()
+                                           .collect();
+            assert_eq!(idents, vec!["origin/pkg", "origin/other"]);
+            assert_eq!(channels_from_matches_or_default(&matches),
+                       vec![ChannelIdent::from("hotfix")]);
+        }
+    }
+
     mod resolve_listen_ctl_addr {
         use super::*;
 
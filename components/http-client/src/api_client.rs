@@ -1,4 +1,5 @@
-use std::{fs,
+use std::{fmt,
+          fs,
           iter::FromIterator,
           path::{Path,
                  PathBuf},
@@ -9,6 +10,7 @@ use reqwest::{header::{HeaderMap,
                        HeaderValue,
                        USER_AGENT},
               Certificate,
+              Identity,
               IntoUrl,
               Proxy,
               RequestBuilder};
@@ -28,9 +30,108 @@ use crate::error::{Error,
 // Read and write TCP socket timeout for Hyper/HTTP client calls.
 const CLIENT_SOCKET_RW_TIMEOUT_SEC: u64 = 300;
 
+// Names a token to append to the User-Agent header of every request, such as the deployment or
+// region requests come from.
+const USER_AGENT_EXTRA_ENVVAR: &str = "HAB_CLIENT_USER_AGENT_EXTRA";
+
+// Names a PKCS #12 file holding a client certificate to present to servers that require mutual
+// TLS, and the password that opens it.
+const CLIENT_IDENTITY_ENVVAR: &str = "HAB_CLIENT_IDENTITY";
+const CLIENT_IDENTITY_PASSWORD_ENVVAR: &str = "HAB_CLIENT_IDENTITY_PASSWORD";
+
 const CACERTS_PKG_IDENT: &str = "core/cacerts";
 const CACERT_PEM: &str = include_str!(concat!(env!("OUT_DIR"), "/cacert.pem"));
 
+/// Settings used to build an `ApiClient`.
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    product:          String,
+    version:          String,
+    user_agent_extra: Option<String>,
+    identity:         Option<ClientIdentity>,
+}
+
+impl ClientConfig {
+    /// Creates a configuration identifying requests as coming from the given product and version.
+    pub fn new(product: &str, version: &str) -> Self {
+        ClientConfig { product:          product.to_string(),
+                       version:          version.to_string(),
+                       user_agent_extra: None,
+                       identity:         None, }
+    }
+
+    /// Like `ClientConfig::new`, but also applies the settings given in the environment. A
+    /// `User-Agent` token is read from `HAB_CLIENT_USER_AGENT_EXTRA`, and a client certificate
+    /// from the PKCS #12 file named by `HAB_CLIENT_IDENTITY`, opened with
+    /// `HAB_CLIENT_IDENTITY_PASSWORD`.
+    ///
+    /// # Errors
+    ///
+    /// * If a setting in the environment is invalid
+    /// * If the client certificate file cannot be read
+    pub fn from_env(product: &str, version: &str) -> Result<Self> {
+        let mut config = Self::new(product, version);
+        match env::var(USER_AGENT_EXTRA_ENVVAR) {
+            Ok(ref extra) if !extra.trim().is_empty() => config = config.user_agent_extra(extra)?,
+            _ => {}
+        }
+        if let Ok(path) = env::var(CLIENT_IDENTITY_ENVVAR) {
+            let password = env::var(CLIENT_IDENTITY_PASSWORD_ENVVAR).unwrap_or_default();
+            config = config.client_identity(&fs::read(path)?, &password)?;
+        }
+        Ok(config)
+    }
+
+    /// Appends `extra` to the `User-Agent` HTTP header, e.g. to name the deployment or region
+    /// requests come from so they can be correlated in Builder's logs.
+    ///
+    /// # Errors
+    ///
+    /// * If `extra` contains newlines or other control characters
+    pub fn user_agent_extra(mut self, extra: &str) -> Result<Self> {
+        if extra.chars().any(char::is_control) {
+            return Err(Error::InvalidUserAgent(extra.to_string()));
+        }
+        self.user_agent_extra = Some(extra.trim().to_string());
+        Ok(self)
+    }
+
+    /// Presents a client certificate to servers that require mutual TLS. `pkcs12_der` is a
+    /// PKCS #12 archive of the certificate and its private key, protected by `password`, which is
+    /// the one form every platform's TLS library accepts. One can be made from PEM files with
+    /// `openssl pkcs12 -export -in cert.pem -inkey key.pem -out identity.p12`.
+    ///
+    /// # Errors
+    ///
+    /// * If `pkcs12_der` is not a PKCS #12 archive that `password` opens
+    pub fn client_identity(mut self, pkcs12_der: &[u8], password: &str) -> Result<Self> {
+        let identity = ClientIdentity { pkcs12_der: pkcs12_der.to_vec(),
+                                        password:   password.to_string(), };
+        identity.to_identity()?;
+        self.identity = Some(identity);
+        Ok(self)
+    }
+}
+
+/// A client certificate and its private key, as given to `ClientConfig::client_identity`.
+#[derive(Clone)]
+struct ClientIdentity {
+    pkcs12_der: Vec<u8>,
+    password:   String,
+}
+
+impl ClientIdentity {
+    fn to_identity(&self) -> Result<Identity> {
+        Identity::from_pkcs12_der(&self.pkcs12_der, &self.password)
+            .map_err(|err| Error::InvalidClientCertificate(err.to_string()))
+    }
+}
+
+// Keeps the private key and its password out of logs
+impl fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { write!(f, "ClientIdentity {{ .. }}") }
+}
+
 /// A generic wrapper around a Reqwest HTTP client intended for API-like usage.
 ///
 /// When an `ApiClient` is created, it has a constant URL base which is assumed to be some API
@@ -56,12 +157,28 @@ impl ApiClient {
     /// * If a suitable SSL context cannot be established
     /// * If an HTTP/S proxy cannot be correctly setup
     /// * If a `User-Agent` HTTP header string cannot be constructed
+    /// * If the configured client certificate cannot be loaded
+    /// * If the settings read by `ClientConfig::from_env` are invalid
     pub fn new<T>(endpoint: T,
                   product: &str,
                   version: &str,
                   fs_root_path: Option<&Path>)
                   -> Result<Self>
         where T: IntoUrl
+    {
+        Self::with_config(endpoint,
+                          &ClientConfig::from_env(product, version)?,
+                          fs_root_path)
+    }
+
+    /// Creates and returns a new `ApiClient` instance using the given `ClientConfig`.
+    ///
+    /// See `ApiClient::new` for the errors this can return.
+    pub fn with_config<T>(endpoint: T,
+                          config: &ClientConfig,
+                          fs_root_path: Option<&Path>)
+                          -> Result<Self>
+        where T: IntoUrl
     {
         let endpoint = endpoint.into_url().map_err(Error::ReqwestError)?;
 
@@ -79,8 +196,7 @@ impl ApiClient {
         let skip_cert_verify = env::var("HAB_SSL_CERT_VERIFY_NONE").is_ok();
         debug!("Skip cert verification: {}", skip_cert_verify);
 
-        let headers =
-            HeaderMap::from_iter(vec![(USER_AGENT, user_agent(product, version)?)].into_iter());
+        let headers = HeaderMap::from_iter(vec![(USER_AGENT, user_agent(config)?)].into_iter());
 
         let mut client = reqwest::Client::builder().proxy(proxy_for(&endpoint)?)
                                                    .default_headers(headers)
@@ -93,6 +209,10 @@ impl ApiClient {
                                            client.add_root_certificate(cert)
                                        });
 
+        if let Some(ref identity) = config.identity {
+            client = client.identity(identity.to_identity()?);
+        }
+
         Ok(ApiClient { inner: client.build()?,
                        endpoint })
     }
@@ -218,7 +338,7 @@ fn proxy_for(url: &Url) -> reqwest::Result<Proxy> {
 /// The general form for Habitat-related clients are of the following form:
 ///
 /// ```text
-/// <PRODUCT>/<VERSION> (<TARGET>; <KERNEL_RELEASE>)
+/// <PRODUCT>/<VERSION> (<TARGET>; <KERNEL_RELEASE>) [<EXTRA>]
 /// ```
 ///
 /// where:
@@ -227,6 +347,7 @@ fn proxy_for(url: &Url) -> reqwest::Result<Proxy> {
 /// * `<VERSION>`: is the provided version string which may also include a release number
 /// * `<TARGET>`: is the machine architecture and the kernel separated by a dash in lower case
 /// * `<KERNEL_RELEASE>`: is the kernel release string from `uname`
+/// * `<EXTRA>`: is the optional `ClientConfig::user_agent_extra` token
 ///
 /// For example:
 ///
@@ -237,15 +358,19 @@ fn proxy_for(url: &Url) -> reqwest::Result<Proxy> {
 /// # Errors
 ///
 /// * If system information cannot be obtained via `uname`
-fn user_agent(product: &str, version: &str) -> Result<HeaderValue> {
+/// * If the `ClientConfig::user_agent_extra` token contains characters not allowed in HTTP headers
+fn user_agent(config: &ClientConfig) -> Result<HeaderValue> {
     let uname = sys::uname()?;
-    let ua = format!("{}/{} ({}; {})",
-                     product.trim(),
-                     version.trim(),
-                     PackageTarget::active_target(),
-                     uname.release.trim().to_lowercase());
+    let mut ua = format!("{}/{} ({}; {})",
+                         config.product.trim(),
+                         config.version.trim(),
+                         PackageTarget::active_target(),
+                         uname.release.trim().to_lowercase());
+    if let Some(ref extra) = config.user_agent_extra {
+        ua = format!("{} {}", ua, extra);
+    }
     debug!("User-Agent: {}", &ua);
-    Ok(HeaderValue::from_str(&ua).expect("Valid User Agent header"))
+    HeaderValue::from_str(&ua).map_err(|_| Error::InvalidUserAgent(ua))
 }
 
 /// We need a set of root certificates when connected to SSL/TLS web endpoints.
@@ -14,6 +14,8 @@ pub type Result<T> = result::Result<T, Error>;
 #[derive(Debug)]
 pub enum Error {
     HabitatCore(hab_core::Error),
+    InvalidClientCertificate(String),
+    InvalidUserAgent(String),
     ReqwestError(reqwest::Error),
     IO(io::Error),
     Json(serde_json::Error),
@@ -24,6 +26,12 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let msg = match *self {
             Error::HabitatCore(ref e) => format!("{}", e),
+            Error::InvalidClientCertificate(ref e) => {
+                format!("Unable to load the client certificate: {}", e)
+            }
+            Error::InvalidUserAgent(ref ua) => {
+                format!("'{}' is not a valid User-Agent header value", ua)
+            }
             Error::ReqwestError(ref err) => format!("{}", err),
             Error::IO(ref e) => format!("{}", e),
             Error::Json(ref e) => format!("{}", e),
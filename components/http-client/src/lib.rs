@@ -4,6 +4,7 @@ extern crate log;
 mod api_client;
 mod error;
 
-pub use crate::{api_client::ApiClient,
+pub use crate::{api_client::{ApiClient,
+                             ClientConfig},
                 error::{Error,
                         Result}};
@@ -42,6 +42,7 @@ use std::{collections::HashMap,
           fs as stdfs,
           path::{Path,
                  PathBuf},
+          slice,
           str::FromStr};
 use tempfile::TempDir;
 
@@ -346,7 +347,7 @@ impl<'a> BuildSpec<'a> {
         let package_install =
             common::command::package::install::start(ui,
                                                      url,
-                                                     channel,
+                                                     slice::from_ref(channel),
                                                      &install_source,
                                                      &*PROGRAM_NAME,
                                                      VERSION,
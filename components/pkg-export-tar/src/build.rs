@@ -20,7 +20,8 @@ use std::os::unix::fs::symlink;
 #[cfg(windows)]
 use std::os::windows::fs::symlink_dir as symlink;
 use std::{fs as stdfs,
-          path::Path};
+          path::Path,
+          slice};
 use tempfile::TempDir;
 
 use super::{BUSYBOX_IDENT,
@@ -188,7 +189,7 @@ impl<'a> BuildSpec<'a> {
         let package_install =
             common::command::package::install::start(ui,
                                                      url,
-                                                     channel,
+                                                     slice::from_ref(channel),
                                                      &install_source,
                                                      &*PROGRAM_NAME,
                                                      VERSION,
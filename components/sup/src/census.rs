@@ -9,7 +9,8 @@ use habitat_butterfly::{member::{Health,
                                 service::{Service as ServiceRumor,
                                           SysInfo},
                                 service_config::ServiceConfig as ServiceConfigRumor,
-                                service_file::ServiceFile as ServiceFileRumor,
+                                service_file::{self,
+                                               ServiceFile as ServiceFileRumor},
                                 ConstIdRumor as _,
                                 RumorStore}};
 use habitat_common::outputln;
@@ -144,7 +145,7 @@ impl CensusRing {
             }
         }
 
-        member_list.with_memberships_mlr(|Membership { member, health }| {
+        member_list.with_memberships_mlr(|Membership { member, health, .. }| {
                        for group in self.census_groups.values_mut() {
                            if let Some(census_member) = group.find_member_mut(&member.id) {
                                census_member.update_from_member(&member);
@@ -480,7 +481,14 @@ impl CensusGroup {
                                        service_file_rumors: &HashMap<String, ServiceFileRumor>)
     {
         self.changed_service_files.clear();
-        for (_m_id, service_file_rumor) in service_file_rumors.iter() {
+        for service_file_rumor in service_file::reassemble(service_file_rumors.values()) {
+            let service_file_rumor = match service_file_rumor {
+                Ok(service_file_rumor) => service_file_rumor,
+                Err(err) => {
+                    warn!("{}", err);
+                    continue;
+                }
+            };
             let filename = service_file_rumor.filename.to_string();
             let file = self.service_files
                            .entry(filename.clone())
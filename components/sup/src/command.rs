@@ -1,3 +1,4 @@
 //! The CLI commands.
 
+pub mod dat_file;
 pub mod shell;
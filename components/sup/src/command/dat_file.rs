@@ -0,0 +1,152 @@
+use crate::error::{Error,
+                   Result};
+use habitat_butterfly::rumor::dat_file::{BundleFormat,
+                                         DatFileDump,
+                                         DatFileReader,
+                                         DatFileWriter};
+use habitat_common::outputln;
+use habitat_core::crypto::SymKey;
+use serde_json;
+use std::{fmt,
+          io::{self,
+               Write},
+          path::PathBuf};
+
+/// Our output key
+static LOGKEY: &str = "DF";
+
+/// Print the header of the dat file at `path` and every rumor in it, either as JSON or as a
+/// listing for people to read. A sealed dat file is opened with `ring_key`.
+pub fn inspect(path: PathBuf, json: bool, ring_key: Option<SymKey>) -> Result<()> {
+    let dump = read(path, ring_key)?.dump()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if json {
+        serde_json::to_writer_pretty(&mut out, &dump).map_err(Error::DatFileSerializationError)?;
+        writeln!(out)?;
+    } else {
+        write_listing(&mut out, &dump)?;
+    }
+    Ok(())
+}
+
+/// Write the members and rumors in the dat file at `path` to a bundle at `bundle`, leaving out
+/// the member that wrote the dat file. A sealed dat file is opened with `ring_key`.
+pub fn export(path: PathBuf,
+              bundle: PathBuf,
+              format: BundleFormat,
+              ring_key: Option<SymKey>)
+              -> Result<()> {
+    read(path, ring_key)?.export(&bundle, format)?;
+    outputln!("Exported dat file to {}", bundle.display());
+    Ok(())
+}
+
+/// Write the dat file at `path` from the bundle at `bundle`.
+pub fn import(bundle: PathBuf, path: PathBuf, format: BundleFormat) -> Result<()> {
+    let count = DatFileWriter::new(path.clone()).import(&bundle, format)?;
+    outputln!("Imported {} members and rumors into {}",
+              count,
+              path.display());
+    Ok(())
+}
+
+fn read(path: PathBuf, ring_key: Option<SymKey>) -> Result<DatFileReader> {
+    let mut reader = DatFileReader::read(path)?;
+    reader.set_seal_key(ring_key);
+    Ok(reader)
+}
+
+fn write_listing(out: &mut dyn Write, dump: &DatFileDump) -> io::Result<()> {
+    let header = &dump.header;
+    writeln!(out, "[header]")?;
+    writeln!(out, "version: {}", header.version)?;
+    writeln!(out, "header size: {}", header.header_size)?;
+    writeln!(out, "file size: {}", header.file_size)?;
+    writeln!(out,
+             "member id: {}",
+             header.member_id.as_ref().map(String::as_str).unwrap_or("-"))?;
+    writeln!(out, "compressed: {}", header.compressed)?;
+    writeln!(out, "sealed: {}", header.sealed)?;
+    if let Some(member_offset) = header.member_offset {
+        writeln!(out, "member region: {} bytes", member_offset)?;
+    }
+    let mut regions: Vec<_> = header.offsets.iter().collect();
+    regions.sort();
+    for (message_id, offset) in regions {
+        writeln!(out, "{} region: {} bytes", message_id, offset)?;
+    }
+
+    write_rumors(out, "members", &dump.members)?;
+    write_rumors(out, "services", &dump.services)?;
+    write_rumors(out, "service configs", &dump.service_configs)?;
+    write_rumors(out, "service files", &dump.service_files)?;
+    write_rumors(out, "elections", &dump.elections)?;
+    write_rumors(out, "election updates", &dump.election_updates)?;
+    write_rumors(out, "departures", &dump.departures)
+}
+
+fn write_rumors<T: fmt::Display>(out: &mut dyn Write, name: &str, rumors: &[T]) -> io::Result<()> {
+    writeln!(out)?;
+    writeln!(out, "[{}] ({})", name, rumors.len())?;
+    for rumor in rumors {
+        writeln!(out, "{}", rumor)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use habitat_butterfly::{error::Error as ButterflyError,
+                            member::MemberList,
+                            rumor::{RumorStore,
+                                    ServiceConfig}};
+    use habitat_core::service::ServiceGroup;
+    use std::{path::Path,
+              str::FromStr};
+    use tempfile::TempDir;
+
+    /// Write a dat file holding one service config, sealed with `key`.
+    fn write_sealed(path: &Path, key: &SymKey) {
+        let service_config_store = RumorStore::default();
+        service_config_store.insert_rsw(ServiceConfig::new("member-a",
+                                                           ServiceGroup::from_str("group.default")
+                                                                .unwrap(),
+                                                           b"password = \"hunter2\"".to_vec()));
+        let mut writer = DatFileWriter::new(path.to_path_buf());
+        writer.set_seal_key(Some(key.clone()));
+        writer.write_rsr_mlr(&MemberList::new(),
+                             &RumorStore::default(),
+                             &service_config_store,
+                             &RumorStore::default(),
+                             &RumorStore::default(),
+                             &RumorStore::default(),
+                             &RumorStore::default())
+              .expect("dat file written");
+    }
+
+    #[test]
+    fn sealed_dat_files_are_read_with_the_ring_key() {
+        let dir = TempDir::new().expect("temp dir created");
+        let dat_file = dir.path().join("sealed.rst");
+        let bundle = dir.path().join("bundle.json");
+        let key = SymKey::generate_pair_for_ring("seal").expect("key generated");
+        write_sealed(&dat_file, &key);
+
+        match export(dat_file.clone(), bundle.clone(), BundleFormat::Json, None) {
+            Err(Error::ButterflyError(ButterflyError::DatFileSealed(_))) => (),
+            other => panic!("Expected DatFileSealed, got {:?}", other),
+        }
+
+        inspect(dat_file.clone(), true, Some(key.clone())).expect("sealed dat file inspected");
+        export(dat_file, bundle.clone(), BundleFormat::Json, Some(key)).expect("exported");
+        let imported = dir.path().join("imported.rst");
+        import(bundle, imported.clone(), BundleFormat::Json).expect("bundle imported");
+        let configs = DatFileReader::read(imported).expect("imported dat file opened")
+                                                   .read_rumors::<ServiceConfig>()
+                                                   .expect("rumors read");
+        assert_eq!(configs.len(), 1);
+        assert_eq!(configs[0].config, b"password = \"hunter2\"".to_vec());
+    }
+}
@@ -46,6 +46,7 @@ pub enum Error {
     TestBootFail,
     ButterflyError(habitat_butterfly::error::Error),
     CtlSecretIo(PathBuf, io::Error),
+    DatFileSerializationError(serde_json::Error),
     APIClient(habitat_api_client::Error),
     EnvJoinPathsError(env::JoinPathsError),
     ExecCommandNotFound(String),
@@ -145,6 +146,9 @@ impl fmt::Display for Error {
             Error::LockPoisoned => "A mutex or read/write lock has failed.".to_string(),
             Error::TestBootFail => "Simulated boot failure".to_string(),
             Error::ButterflyError(ref err) => format!("Butterfly error: {}", err),
+            Error::DatFileSerializationError(ref e) => {
+                format!("Can't serialize dat file contents: {}", e)
+            }
             Error::CtlSecretIo(ref path, ref err) => {
                 format!("IoError while reading or writing ctl secret, {}, {}",
                         path.display(),
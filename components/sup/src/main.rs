@@ -27,6 +27,9 @@ use crate::sup::{cli::cli,
                  util};
 use clap::ArgMatches;
 use hab::cli::parse_optional_arg;
+use habitat_butterfly::{rumor::dat_file::BundleFormat,
+                        server::{timing::Timing,
+                                 PersistConfig}};
 use habitat_common::{cli::cache_key_path_from_matches,
                      command::package::install::InstallSource,
                      liveliness_checker,
@@ -64,7 +67,8 @@ use std::{env,
                  PathBuf},
           process,
           str::{self,
-                FromStr}};
+                FromStr},
+          time::Duration};
 #[cfg(test)]
 use tempfile::TempDir;
 
@@ -146,6 +150,14 @@ fn start_rsr_imlw_mlw_gsw_smw_rhw_msw(feature_flags: FeatureFlag) -> Result<()>
     };
     match app_matches.subcommand() {
         ("bash", Some(_)) => sub_bash(),
+        ("dat-file", Some(m)) => {
+            match m.subcommand() {
+                ("inspect", Some(m)) => sub_dat_file_inspect(m),
+                ("export", Some(m)) => sub_dat_file_export(m),
+                ("import", Some(m)) => sub_dat_file_import(m),
+                _ => unreachable!(),
+            }
+        }
         ("run", Some(m)) => {
             let launcher = launcher.ok_or(Error::NoLauncher)?;
             sub_run_rsr_imlw_mlw_gsw_smw_rhw_msw(m, launcher, feature_flags)
@@ -158,6 +170,37 @@ fn start_rsr_imlw_mlw_gsw_smw_rhw_msw(feature_flags: FeatureFlag) -> Result<()>
 
 fn sub_bash() -> Result<()> { command::shell::bash() }
 
+fn sub_dat_file_inspect(m: &ArgMatches) -> Result<()> {
+    let path = m.value_of("DAT_FILE").expect("DAT_FILE is required");
+    command::dat_file::inspect(PathBuf::from(path),
+                               m.is_present("JSON"),
+                               get_ring_key(m, &cache_key_path_from_matches(m))?)
+}
+
+fn sub_dat_file_export(m: &ArgMatches) -> Result<()> {
+    let path = m.value_of("DAT_FILE").expect("DAT_FILE is required");
+    let bundle = m.value_of("BUNDLE").expect("BUNDLE is required");
+    command::dat_file::export(PathBuf::from(path),
+                              PathBuf::from(bundle),
+                              bundle_format_from_matches(m),
+                              get_ring_key(m, &cache_key_path_from_matches(m))?)
+}
+
+fn sub_dat_file_import(m: &ArgMatches) -> Result<()> {
+    let bundle = m.value_of("BUNDLE").expect("BUNDLE is required");
+    let path = m.value_of("DAT_FILE").expect("DAT_FILE is required");
+    command::dat_file::import(PathBuf::from(bundle),
+                              PathBuf::from(path),
+                              bundle_format_from_matches(m))
+}
+
+fn bundle_format_from_matches(m: &ArgMatches) -> BundleFormat {
+    match m.value_of("FORMAT") {
+        Some("protobuf") => BundleFormat::Protobuf,
+        _ => BundleFormat::Json,
+    }
+}
+
 /// # Locking (see locking.md)
 /// * `RumorStore::list` (read)
 /// * `MemberList::initial_members` (write)
@@ -274,11 +317,43 @@ fn mgrcfg_from_sup_run_matches(m: &ArgMatches,
         }),
         feature_flags,
         event_stream_config,
+        persist_config: persist_config_from_matches(m),
+        gossip_timing: gossip_timing_from_matches(m)?,
+        zone: m.value_of("ZONE").map(str::to_string),
     };
 
     Ok(cfg)
 }
 
+/// The `PersistConfig` from the environment, with any of it overridden on the command line.
+fn persist_config_from_matches(m: &ArgMatches) -> PersistConfig {
+    let mut config = PersistConfig::from_env();
+    if let Some(interval) = parse_optional_arg::<u64>("PERSIST_INTERVAL", m) {
+        config.interval = Duration::from_secs(interval);
+    }
+    if let Some(after_changes) = parse_optional_arg::<usize>("PERSIST_AFTER_CHANGES", m) {
+        config.after_changes = Some(after_changes).filter(|&changes| changes > 0);
+    }
+    if m.is_present("NO_PERSIST_ON_SHUTDOWN") {
+        config.on_shutdown = false;
+    }
+    config
+}
+
+/// The default `Timing`, with the probe interval and timeout overrides set on the command line.
+fn gossip_timing_from_matches(m: &ArgMatches) -> Result<Timing> {
+    let mut timing = Timing::default();
+    if let Some(probe_interval_ms) = parse_optional_arg::<i64>("PROBE_INTERVAL", m) {
+        timing.probe_interval_ms = probe_interval_ms;
+    }
+    if let Some(overrides) = m.values_of("TIMEOUT_OVERRIDE") {
+        for timeout_override in overrides {
+            timing.timeout_overrides.push(timeout_override.parse()?);
+        }
+    }
+    Ok(timing)
+}
+
 // Various CLI Parsing Functions
 ////////////////////////////////////////////////////////////////////////
 
@@ -481,6 +556,8 @@ fn svc_load_from_input(m: &ArgMatches) -> Result<sup_proto::ctl::SvcLoad> {
 #[cfg(test)]
 mod test {
     use super::*;
+    use habitat_butterfly::server::timing::{MemberSelector,
+                                            TimeoutOverride};
     use habitat_common::{locked_env_var,
                          types::{GossipListenAddr,
                                  HttpListenAddr,
@@ -586,6 +663,77 @@ mod test {
             assert_eq!(config.http_disable, false);
         }
 
+        #[test]
+        fn persist_config_should_be_set() {
+            let config = config_from_cmd_str("hab-sup run --persist-interval 5 \
+                                              --persist-after-changes 100 \
+                                              --no-persist-on-shutdown");
+            assert_eq!(config.persist_config.interval, Duration::from_secs(5));
+            assert_eq!(config.persist_config.after_changes, Some(100));
+            assert_eq!(config.persist_config.on_shutdown, false);
+        }
+
+        #[test]
+        fn probe_interval_should_be_set() {
+            let config = config_from_cmd_str("hab-sup run --probe-interval 500");
+            assert_eq!(config.gossip_timing.probe_interval_ms, 500);
+
+            let config = config_from_cmd_str("hab-sup run");
+            assert_eq!(config.gossip_timing.probe_interval_ms,
+                       Timing::default().probe_interval_ms);
+        }
+
+        #[test]
+        fn zone_should_be_set() {
+            let config = config_from_cmd_str("hab-sup run --zone eu-west");
+            assert_eq!(config.zone, Some("eu-west".to_string()));
+
+            let config = config_from_cmd_str("hab-sup run");
+            assert_eq!(config.zone, None);
+        }
+
+        #[test]
+        fn timeout_overrides_should_be_set() {
+            let config = config_from_cmd_str("hab-sup run --timeout-override \
+                                              label:zone=eu-west,3,2 --timeout-override \
+                                              address:10.1.,1.5,1");
+            let overrides = config.gossip_timing.timeout_overrides;
+            assert_eq!(overrides,
+                       vec![TimeoutOverride::new(MemberSelector::Label("zone".to_string(),
+                                                                       "eu-west".to_string()),
+                                                 3.0,
+                                                 2.0).unwrap(),
+                            TimeoutOverride::new(MemberSelector::AddressPrefix("10.1."
+                                                                                   .to_string()),
+                                                 1.5,
+                                                 1.0).unwrap(),]);
+        }
+
+        #[test]
+        fn timeout_override_multipliers_must_be_positive() {
+            let cmd_vec =
+                cmd_vec_from_cmd_str("hab-sup run --timeout-override label:zone=eu-west,0,2");
+            let matches = cli(no_feature_flags()).get_matches_from_safe(cmd_vec)
+                                                 .expect("Error while getting matches");
+            let (_, sub_matches) = matches.subcommand();
+            let sub_matches = sub_matches.expect("Error getting sub command matches");
+            assert!(mgrcfg_from_sup_run_matches(&sub_matches, no_feature_flags()).is_err());
+        }
+
+        #[test]
+        fn probe_interval_must_be_positive() {
+            let cmd_vec = cmd_vec_from_cmd_str("hab-sup run --probe-interval 0");
+            assert!(cli(no_feature_flags()).get_matches_from_safe(cmd_vec)
+                                           .is_err());
+        }
+
+        #[test]
+        fn persist_interval_must_be_positive() {
+            let cmd_vec = cmd_vec_from_cmd_str("hab-sup run --persist-interval 0");
+            assert!(cli(no_feature_flags()).get_matches_from_safe(cmd_vec)
+                                           .is_err());
+        }
+
         #[test]
         fn ctl_listen_should_be_set() {
             let config = config_from_cmd_str("hab-sup run --listen-ctl 3.3.3.3:3333");
@@ -701,4 +849,36 @@ RCFaO84j41GmrzWddxMdsXpGdn3iuIy7Mw3xYrjPLsE="#,
                        "foobar-20160504220722");
         }
     }
+
+    mod dat_file {
+        use super::*;
+
+        fn bundle_format_from_cmd_str(cmd: &str) -> BundleFormat {
+            let matches = cli(no_feature_flags()).get_matches_from_safe(cmd.split_whitespace())
+                                                 .expect("Error while getting matches");
+            let (_, dat_file_matches) = matches.subcommand();
+            let (_, sub_matches) = dat_file_matches.expect("Error getting dat-file matches")
+                                                   .subcommand();
+            bundle_format_from_matches(sub_matches.expect("Error getting sub command matches"))
+        }
+
+        #[test]
+        fn bundle_format_should_be_set() {
+            let dat_file = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            let cmd = format!("hab-sup dat-file export {} bundle.pb --format protobuf",
+                              dat_file);
+            assert_eq!(bundle_format_from_cmd_str(&cmd), BundleFormat::Protobuf);
+
+            let cmd = format!("hab-sup dat-file export {} bundle.json", dat_file);
+            assert_eq!(bundle_format_from_cmd_str(&cmd), BundleFormat::Json);
+        }
+
+        #[test]
+        fn bundle_format_must_be_json_or_protobuf() {
+            let dat_file = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            let cmd = format!("hab-sup dat-file export {} bundle --format yaml", dat_file);
+            assert!(cli(no_feature_flags()).get_matches_from_safe(cmd.split_whitespace())
+                                           .is_err());
+        }
+    }
 }
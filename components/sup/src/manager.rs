@@ -47,8 +47,10 @@ use futures::{future,
               prelude::*,
               sync::{mpsc as fut_mpsc,
                      oneshot}};
-use habitat_butterfly::{member::Member,
+use habitat_butterfly::{member::{Member,
+                                 ZONE_LABEL},
                         server::{timing::Timing,
+                                 PersistConfig,
                                  ServerProxy,
                                  Suitability}};
 use habitat_common::{liveliness_checker,
@@ -239,6 +241,11 @@ pub struct ManagerConfig {
     pub tls_config:          Option<TLSConfig>,
     pub feature_flags:       FeatureFlag,
     pub event_stream_config: Option<EventStreamConfig>,
+    pub persist_config:      PersistConfig,
+    pub gossip_timing:       Timing,
+    /// The zone this Supervisor runs in, which its gossip prefers to reach first. See
+    /// `Server::gossip_peers_for_round_mlr_smr`.
+    pub zone:                Option<String>,
 }
 
 #[derive(Clone, Debug)]
@@ -509,6 +516,7 @@ pub struct Manager {
     service_states:      HashMap<PackageIdent, Timespec>,
     sys:                 Arc<Sys>,
     http_disable:        bool,
+    gossip_timing:       Timing,
 
     /// Collects the identifiers of all services that are currently
     /// doing something asynchronously (like shutting down, or running
@@ -595,17 +603,18 @@ impl Manager {
                                cfg.gossip_listen,
                                cfg.ctl_listen,
                                cfg.http_listen)?;
-        let member = Self::load_member(&mut sys, &fs_cfg)?;
+        let member = Self::load_member(&mut sys, &fs_cfg, cfg.zone.as_ref())?;
         let services = Arc::default();
         let suitability_lookup = Arc::clone(&services) as Arc<dyn Suitability>;
 
-        let server = habitat_butterfly::Server::new(sys.gossip_listen(),
-                                                    sys.gossip_listen(),
-                                                    member,
-                                                    cfg.ring_key,
-                                                    None,
-                                                    Some(&fs_cfg.data_path),
-                                                    suitability_lookup)?;
+        let mut server = habitat_butterfly::Server::new(sys.gossip_listen(),
+                                                        sys.gossip_listen(),
+                                                        member,
+                                                        cfg.ring_key,
+                                                        None,
+                                                        Some(&fs_cfg.data_path),
+                                                        suitability_lookup)?;
+        server.set_persist_config(cfg.persist_config);
         outputln!("Supervisor Member-ID {}", sys.member_id);
         for peer_addr in &cfg.gossip_peers {
             let mut peer = Member::default();
@@ -665,6 +674,7 @@ impl Manager {
                      service_states: HashMap::new(),
                      sys: Arc::new(sys),
                      http_disable: cfg.http_disable,
+                     gossip_timing: cfg.gossip_timing,
                      busy_services: Arc::new(Mutex::new(HashSet::new())),
                      services_need_reconciliation: ReconciliationFlag::new(false),
                      feature_flags: cfg.feature_flags,
@@ -682,7 +692,7 @@ impl Manager {
     // in there, so splitting the initialization is needlessly
     // confusing. It's also blurs the lines between the manager and
     // Butterfly.
-    fn load_member(sys: &mut Sys, fs_cfg: &FsCfg) -> Result<Member> {
+    fn load_member(sys: &mut Sys, fs_cfg: &FsCfg, zone: Option<&String>) -> Result<Member> {
         let mut member = Member::default();
         match File::open(&fs_cfg.member_id_file) {
             Ok(mut file) => {
@@ -708,6 +718,9 @@ impl Manager {
         }
         sys.member_id = member.id.to_string();
         member.persistent = sys.permanent;
+        if let Some(zone) = zone {
+            member.labels.insert(ZONE_LABEL.to_string(), zone.clone());
+        }
         Ok(member)
     }
 
@@ -875,7 +888,7 @@ impl Manager {
         outputln!("Starting gossip-listener on {}",
                   self.butterfly.gossip_addr());
         self.butterfly
-            .start_rsw_mlw_smw_rhw_msr(&Timing::default())?;
+            .start_rsw_mlw_smw_rhw_msr(&self.gossip_timing)?;
         debug!("gossip-listener started");
         self.persist_state_rsr_mlr_gsw_msr();
         let http_listen_addr = self.sys.http_listen();
@@ -1018,10 +1031,15 @@ impl Manager {
             #[cfg(unix)]
             match self.feature_flags.contains(FeatureFlag::IGNORE_SIGNALS) {
                 false => {
-                    if let Some(SignalEvent::Passthrough(Signal::HUP)) = signals::check_for_signal()
-                    {
-                        outputln!("Supervisor shutting down for signal");
-                        break ShutdownMode::Restarting;
+                    match signals::check_for_signal() {
+                        Some(SignalEvent::Passthrough(Signal::HUP)) => {
+                            outputln!("Supervisor shutting down for signal");
+                            break ShutdownMode::Restarting;
+                        }
+                        Some(SignalEvent::Passthrough(Signal::USR1)) => {
+                            self.dump_diagnostics_rsr_mlr_smr();
+                        }
+                        _ => {}
                     }
                 }
                 _ => {}
@@ -1167,7 +1185,7 @@ impl Manager {
             .expect("Error waiting on Tokio runtime to shutdown");
 
         release_process_lock(&self.fs_cfg);
-        self.butterfly.persist_data_rsr_mlr();
+        self.butterfly.persist_on_shutdown_rsr_mlr();
 
         match shutdown_mode {
             ShutdownMode::Normal | ShutdownMode::Restarting => Ok(()),
@@ -1266,6 +1284,31 @@ impl Manager {
 
     fn check_for_departure(&self) -> bool { self.butterfly.is_departed() }
 
+    /// Write a snapshot of the butterfly server's state to a file in the system temp directory so
+    /// it can be inspected without restarting the Supervisor. This is triggered by SIGUSR1, so it
+    /// is not available on Windows; use the HTTP gateway's `/butterfly` endpoint there instead.
+    ///
+    /// # Locking (see locking.md)
+    /// * `RumorStore::list` (read)
+    /// * `MemberList::entries` (read)
+    /// * `Server::member` (read)
+    #[cfg(unix)]
+    fn dump_diagnostics_rsr_mlr_smr(&self) {
+        let file_name = format!("hab-sup-diagnostics-{}.txt", time::get_time().sec);
+        let path = std::env::temp_dir().join(file_name);
+        let result = File::create(&path).and_then(|mut file| {
+                                            self.butterfly.dump_diagnostics_rsr_mlr_smr(&mut file)
+                                        });
+        match result {
+            Ok(()) => outputln!("Wrote Supervisor diagnostics to {}", path.display()),
+            Err(err) => {
+                outputln!("Unable to write Supervisor diagnostics to {}: {}",
+                          path.display(),
+                          err)
+            }
+        }
+    }
+
     /// # Locking (see locking.md)
     /// * `ManagerServices::inner` (read)
     fn check_for_changed_services_msr(&mut self) -> bool {
@@ -1943,7 +1986,10 @@ mod test {
                             watch_peer_file:     None,
                             tls_config:          None,
                             feature_flags:       FeatureFlag::empty(),
-                            event_stream_config: None, }
+                            event_stream_config: None,
+                            persist_config:      PersistConfig::default(),
+                            gossip_timing:       Timing::default(),
+                            zone:                None, }
         }
     }
 
@@ -171,7 +171,14 @@ pub fn service_file_put(mgr: &ManagerState,
             }
         };
     client.send_service_file(service_group, filename, version, &content, is_encrypted)
-          .map_err(|e| net::err(ErrCode::Internal, e.to_string()))
+          .map_err(|e| {
+              match e {
+                  butterfly::error::Error::ServiceFileTooLarge { .. } => {
+                      net::err(ErrCode::EntityTooLarge, e.to_string())
+                  }
+                  _ => net::err(ErrCode::Internal, e.to_string()),
+              }
+          })
           .map(|_| {
               req.reply_complete(net::ok());
           })
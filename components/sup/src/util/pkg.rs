@@ -16,7 +16,8 @@ use habitat_core::{env as henv,
                              PackageInstall},
                    ChannelIdent,
                    AUTH_TOKEN_ENVVAR};
-use std::path::Path;
+use std::{path::Path,
+          slice};
 
 static LOGKEY: &str = "UT";
 
@@ -36,7 +37,7 @@ pub fn install<T>(ui: &mut T,
     };
     install_cmd::start(ui,
                        url,
-                       channel,
+                       slice::from_ref(channel),
                        install_source,
                        PRODUCT,
                        VERSION,